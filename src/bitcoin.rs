@@ -0,0 +1,439 @@
+use crypto::digest::Digest;
+use hash_function::hash_node;
+use hasher::DoubleSha256;
+use merkle_tree::MerkleTree;
+use merkle_error::MerkleError;
+use proof::{Position, Proof, ProofNode};
+use odd_node_policy::OddNodePolicy;
+use tree_mode::TreeMode;
+
+/// Разбирает хэш Bitcoin из шестнадцатеричной строки в привычном отображении
+/// (little-endian, т.е. в обратном порядке байт) во внутреннее представление.
+pub fn bitcoin_hash_from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (index, byte) in hash.iter_mut().rev().enumerate() {
+        match u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16) {
+            Ok(value) => *byte = value,
+            Err(_) => return None,
+        }
+    }
+    Some(hash)
+}
+
+/// Отображает хэш Bitcoin в виде шестнадцатеричной строки в обратном порядке байт,
+/// как это делают обозреватели блоков и `bitcoind`.
+pub fn bitcoin_hash_to_hex(hash: &[u8; 32]) -> String {
+    hash.iter().rev().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Заголовок блока Bitcoin.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BlockHeader {
+    /// Версия блока.
+    pub version: i32,
+    /// Хэш предыдущего блока во внутреннем порядке байт.
+    pub prev_block: [u8; 32],
+    /// `hashMerkleRoot` во внутреннем порядке байт.
+    pub merkle_root: [u8; 32],
+    /// Время создания блока.
+    pub time: u32,
+    /// Сложность в компактном виде.
+    pub bits: u32,
+    /// Nonce.
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    /// Разбирает 80-байтный сериализованный заголовок блока.
+    pub fn from_bytes(bytes: &[u8]) -> Option<BlockHeader> {
+        if bytes.len() != 80 {
+            return None;
+        }
+        let mut prev_block = [0; 32];
+        prev_block.copy_from_slice(&bytes[4..36]);
+        let mut merkle_root = [0; 32];
+        merkle_root.copy_from_slice(&bytes[36..68]);
+        Some(BlockHeader {
+            version: read_u32(&bytes[0..4]) as i32,
+            prev_block,
+            merkle_root,
+            time: read_u32(&bytes[68..72]),
+            bits: read_u32(&bytes[72..76]),
+            nonce: read_u32(&bytes[76..80]),
+        })
+    }
+
+    /// Сериализует заголовок блока в 80 байт.
+    pub fn to_bytes(&self) -> [u8; 80] {
+        let mut bytes = [0; 80];
+        bytes[0..4].copy_from_slice(&(self.version as u32).to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.prev_block);
+        bytes[36..68].copy_from_slice(&self.merkle_root);
+        bytes[68..72].copy_from_slice(&self.time.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        bytes[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    /// Возвращает хэш блока во внутреннем порядке байт.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut digest = DoubleSha256::new();
+        let mut hash = [0; 32];
+        digest.input(&self.to_bytes());
+        digest.result(&mut hash);
+        hash
+    }
+
+    /// Проверяет, что транзакция входит в блок, используя SPV-ветку.
+    pub fn verify_merkle_branch(&self, txid: &[u8; 32], branch: &MerkleBranch) -> bool {
+        branch.root(txid) == self.merkle_root
+    }
+
+    /// Проверяет, что транзакция входит в блок, используя доказательство `audit_proof` дерева
+    /// блока. Смотри `MerkleBranch::from_proof`.
+    pub fn verify_audit_proof(&self, txid: &[u8; 32], proof: &Proof<DoubleSha256>) -> bool {
+        match MerkleBranch::from_proof(proof, txid) {
+            Some(branch) => self.verify_merkle_branch(txid, &branch),
+            None => false,
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(bytes);
+    u32::from_le_bytes(buffer)
+}
+
+/// SPV-ветка: позиция транзакции в блоке и хэши соседних узлов на пути от транзакции до корня.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MerkleBranch {
+    /// Индекс транзакции в блоке.
+    pub index: usize,
+    /// Хэши соседних узлов, начиная со слоя транзакций.
+    pub hashes: Vec<[u8; 32]>,
+}
+
+impl MerkleBranch {
+    /// Преобразует доказательство включения `audit_proof` дерева блока в SPV-ветку. На слоях,
+    /// где узел оказался последним и был продублирован, `Proof` не хранит соседа, а ветка
+    /// Bitcoin хранит сам узел, поэтому для преобразования нужен идентификатор транзакции.
+    /// Возвращает `None`, если доказательство построено не для дерева блока Bitcoin
+    /// (`MerkleTree::bitcoin`) или не согласуется с `leaf_index` и `tree_size`.
+    pub fn from_proof(proof: &Proof<DoubleSha256>, txid: &[u8; 32]) -> Option<MerkleBranch> {
+        if proof.mode != TreeMode::Classic || proof.odd_node_policy != OddNodePolicy::Duplicate ||
+           proof.leaf_index >= proof.tree_size {
+            return None;
+        }
+        let digest = DoubleSha256::new();
+        let mut path = proof.path.iter();
+        let mut hashes = Vec::with_capacity(proof.path.len() + 1);
+        let mut hash = *txid;
+        let mut index = proof.leaf_index;
+        let mut layer_size = proof.tree_size;
+        while layer_size > 1 {
+            let sibling = if !index.is_multiple_of(2) {
+                match path.next() {
+                    Some(&ProofNode { hash: sibling, position: Position::Left }) => sibling,
+                    _ => return None,
+                }
+            } else if index + 1 < layer_size {
+                match path.next() {
+                    Some(&ProofNode { hash: sibling, position: Position::Right }) => sibling,
+                    _ => return None,
+                }
+            } else {
+                hash
+            };
+            hash = if index.is_multiple_of(2) {
+                hash_node(&digest, TreeMode::Classic, &hash, &sibling)
+            } else {
+                hash_node(&digest, TreeMode::Classic, &sibling, &hash)
+            };
+            hashes.push(sibling);
+            index /= 2;
+            layer_size = layer_size.div_ceil(2);
+        }
+        match path.next() {
+            Some(_) => None,
+            None => Some(MerkleBranch { index: proof.leaf_index, hashes }),
+        }
+    }
+
+    /// Вычисляет `hashMerkleRoot` по ветке для переданного идентификатора транзакции.
+    pub fn root(&self, txid: &[u8; 32]) -> [u8; 32] {
+        let digest = DoubleSha256::new();
+        let mut index = self.index;
+        let mut hash = *txid;
+        for sibling in &self.hashes {
+            hash = if index.is_multiple_of(2) {
+                hash_node(&digest, TreeMode::Classic, &hash, sibling)
+            } else {
+                hash_node(&digest, TreeMode::Classic, sibling, &hash)
+            };
+            index /= 2;
+        }
+        hash
+    }
+}
+
+impl MerkleTree<DoubleSha256> {
+    /// Создаёт пустое дерево, вычисляющее `hashMerkleRoot` блока Bitcoin: узлы хэшируются двойным
+    /// SHA-256, одиночный узел в конце слоя дублируется.
+    /// Идентификаторы транзакций добавляются во внутреннем порядке байт через `push_hash`.
+    pub fn bitcoin() -> MerkleTree<DoubleSha256> {
+        MerkleTree {
+            odd_node_policy: OddNodePolicy::Duplicate,
+            ..MerkleTree::with_digest(DoubleSha256::new())
+        }
+    }
+
+    /// Создаёт дерево блока Bitcoin из идентификаторов транзакций во внутреннем порядке байт.
+    pub fn from_txids(txids: &[[u8; 32]]) -> MerkleTree<DoubleSha256> {
+        let mut merkle_tree = MerkleTree::bitcoin();
+        for txid in txids {
+            merkle_tree.push_hash(*txid);
+        }
+        merkle_tree
+    }
+
+    /// Возвращает SPV-ветку для транзакции, проверяемую по заголовку блока: доказательство
+    /// включения, как у `audit_proof`, преобразованное `MerkleBranch::from_proof`. Само
+    /// доказательство `audit_proof` проверяется по заголовку через `BlockHeader::verify_audit_proof`.
+    ///
+    /// # Failures
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// SPV-ветки определены только для деревьев блока (`MerkleTree::bitcoin`): для дерева в режиме,
+    /// отличном от `TreeMode::Classic`, возвращается `MerkleError::UnsupportedMode`, для правила,
+    /// отличного от `OddNodePolicy::Duplicate` - `MerkleError::UnsupportedPolicy`.
+    /// В случае, если транзакции нет в дереве - `MerkleError::LeafNotFound`.
+    pub fn merkle_branch(&self, txid: &[u8; 32]) -> Result<MerkleBranch, MerkleError> {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        if self.mode != TreeMode::Classic {
+            return Err(MerkleError::UnsupportedMode(self.mode));
+        }
+        if self.odd_node_policy != OddNodePolicy::Duplicate {
+            return Err(MerkleError::UnsupportedPolicy(self.odd_node_policy));
+        }
+        let proof = match self.layers[0].iter().position(|leaf| leaf == txid) {
+            Some(index) => self.proof_by_index(index)?,
+            None => return Err(MerkleError::LeafNotFound),
+        };
+        MerkleBranch::from_proof(&proof, txid).ok_or(MerkleError::InvalidProof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate env_logger;
+    use super::{bitcoin_hash_from_hex, bitcoin_hash_to_hex, BlockHeader, MerkleBranch};
+    use merkle_tree::MerkleTree;
+    use merkle_error::MerkleError;
+    use odd_node_policy::OddNodePolicy;
+    use tree_mode::TreeMode;
+    use hasher::DoubleSha256;
+
+    const BLOCK_100000_TXIDS: [&str; 4] =
+        ["8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+         "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+         "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+         "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d"];
+
+    fn block_100000_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_block: txid("000000000002d01c1fccc21636b607dfd930d31d01c3a62104612a1719011250"),
+            merkle_root: txid("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"),
+            time: 1293623863,
+            bits: 0x1b04864c,
+            nonce: 274148111,
+        }
+    }
+
+    fn txid(hex: &str) -> [u8; 32] {
+        bitcoin_hash_from_hex(hex).unwrap()
+    }
+
+    fn txids(hexes: &[&str]) -> Vec<[u8; 32]> {
+        hexes.iter().map(|hex| txid(hex)).collect()
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let txid = bitcoin_hash_from_hex(BLOCK_100000_TXIDS[0]).unwrap();
+        assert_eq!(0x87, txid[0]);
+        assert_eq!(0x8c, txid[31]);
+        assert_eq!(BLOCK_100000_TXIDS[0], bitcoin_hash_to_hex(&txid));
+        assert_eq!(None, bitcoin_hash_from_hex("8c14"));
+        assert_eq!(None, bitcoin_hash_from_hex(&"zz".repeat(32)));
+        assert_eq!(None, bitcoin_hash_from_hex(&"+f".repeat(32)));
+    }
+
+    #[test]
+    fn block_header_hash() {
+        let header = block_100000_header();
+        assert_eq!("000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506",
+                   bitcoin_hash_to_hex(&header.hash()));
+        assert_eq!(Some(header), BlockHeader::from_bytes(&header.to_bytes()));
+        assert_eq!(None, BlockHeader::from_bytes(&[0; 79]));
+    }
+
+    #[test]
+    fn block_100000_merkle_root() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from_txids(&txids(&BLOCK_100000_TXIDS));
+        let root = merkle_tree.build().unwrap();
+        assert_eq!("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766",
+                   bitcoin_hash_to_hex(&root));
+        assert_eq!(block_100000_header().merkle_root, root);
+    }
+
+    #[test]
+    fn block_170_merkle_root() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from_txids(&txids(&[
+            "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"]));
+        assert_eq!("7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff",
+                   bitcoin_hash_to_hex(&merkle_tree.build().unwrap()));
+    }
+
+    #[test]
+    fn genesis_merkle_root() {
+        let _ = env_logger::init();
+        let coinbase = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let mut merkle_tree = MerkleTree::from_txids(&txids(&[coinbase]));
+        assert_eq!(coinbase, bitcoin_hash_to_hex(&merkle_tree.build().unwrap()));
+        let genesis = BlockHeader {
+            version: 1,
+            prev_block: [0; 32],
+            merkle_root: txid(coinbase),
+            time: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        };
+        assert_eq!("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                   bitcoin_hash_to_hex(&genesis.hash()));
+        let branch = merkle_tree.merkle_branch(&txid(coinbase)).unwrap();
+        assert_eq!(MerkleBranch { index: 0, hashes: vec![] }, branch);
+        assert!(genesis.verify_merkle_branch(&txid(coinbase), &branch));
+    }
+
+    #[test]
+    fn odd_transactions_duplicate_last() {
+        let _ = env_logger::init();
+        // Искусственный набор из трёх транзакций блока 100000, а не реальный блок.
+        let block_txids = txids(&BLOCK_100000_TXIDS[..3]);
+        let mut odd_tree = MerkleTree::from_txids(&block_txids);
+        let mut duplicated_txids = block_txids.clone();
+        duplicated_txids.push(block_txids[2]);
+        let mut duplicated_tree = MerkleTree::from_txids(&duplicated_txids);
+        assert_eq!(duplicated_tree.build().unwrap(), odd_tree.build().unwrap());
+        let mut pushed_tree = MerkleTree::from_txids(&block_txids[..1]);
+        pushed_tree.build().unwrap();
+        pushed_tree.push_hash(block_txids[1]);
+        pushed_tree.push_hash(block_txids[2]);
+        assert_eq!(odd_tree.get_merkle_root(), pushed_tree.get_merkle_root());
+    }
+
+    #[test]
+    fn odd_block_merkle_branch() {
+        let _ = env_logger::init();
+        // Блок 000000000000b731... из семи транзакций: последняя транзакция дублируется, поэтому
+        // соседом узла над транзакциями 4 и 5 является хэш седьмой транзакции с самой собой.
+        let header = BlockHeader {
+            version: 1,
+            prev_block: txid("0000000000016780c81d42b7eff86974c36f5ae026e8662a4393a7f39c86bb82"),
+            merkle_root: txid("8772d9d0fdf8c1303c7b1167e3c73b095fd970e33c799c6563d98b2e96c5167f"),
+            time: 1293629558,
+            bits: 0x1b04864c,
+            nonce: 696601429,
+        };
+        assert_eq!("000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4",
+                   bitcoin_hash_to_hex(&header.hash()));
+        let txid_4 = txid("652b0aa4cf4f17bdb31f7a1d308331bba91f3b3cbf8f39c9cb5e19d4015b9f01");
+        let branch = MerkleBranch {
+            index: 4,
+            hashes: txids(&["68d0685759c3d4f3f90a4f0e48d1b77641f06bb1f0b83a8841e8d71d5570ed41",
+                            "bf9b3759faaeba8fe678ea6931b6f825fe25c40fc81a5b2e30874999bca7d220",
+                            "a2dac5f67058c1c6bf8c243dcec1b143a41975701abe6076e87e042426261236"]),
+        };
+        assert!(header.verify_merkle_branch(&txid_4, &branch));
+        let mut swapped = branch.clone();
+        swapped.index = 5;
+        assert!(!header.verify_merkle_branch(&txid_4, &swapped));
+    }
+
+    #[test]
+    fn spv_merkle_branch() {
+        let _ = env_logger::init();
+        let header = block_100000_header();
+        let block_txids = txids(&BLOCK_100000_TXIDS);
+        let mut merkle_tree = MerkleTree::from_txids(&block_txids);
+        merkle_tree.build().unwrap();
+        for (index, txid) in block_txids.iter().enumerate() {
+            let branch = merkle_tree.merkle_branch(txid).unwrap();
+            assert_eq!(index, branch.index);
+            assert_eq!(2, branch.hashes.len());
+            assert!(header.verify_merkle_branch(txid, &branch));
+            assert!(!header.verify_merkle_branch(&block_txids[(index + 1) % 4], &branch));
+        }
+        let mut odd_tree = MerkleTree::from_txids(&block_txids[..3]);
+        let odd_root = odd_tree.build().unwrap();
+        let branch = odd_tree.merkle_branch(&block_txids[2]).unwrap();
+        assert_eq!(block_txids[2], branch.hashes[0]);
+        assert_eq!(odd_root, branch.root(&block_txids[2]));
+        assert_eq!(Err(MerkleError::LeafNotFound),
+                   odd_tree.merkle_branch(&block_txids[3]));
+    }
+
+    #[test]
+    fn merkle_branch_requires_block_tree() {
+        let _ = env_logger::init();
+        let block_txids = txids(&BLOCK_100000_TXIDS[..3]);
+        let mut rehash_tree = MerkleTree::with_digest(DoubleSha256::new());
+        for txid in &block_txids {
+            rehash_tree.push_hash(*txid);
+        }
+        rehash_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::UnsupportedPolicy(OddNodePolicy::Rehash)),
+                   rehash_tree.merkle_branch(&block_txids[2]));
+
+        let mut rfc6962_tree = MerkleTree::from_txids(&block_txids);
+        rfc6962_tree.mode = TreeMode::Rfc6962;
+        rfc6962_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::UnsupportedMode(TreeMode::Rfc6962)),
+                   rfc6962_tree.merkle_branch(&block_txids[2]));
+    }
+
+    #[test]
+    fn audit_proof_verifies_against_header() {
+        let _ = env_logger::init();
+        let header = block_100000_header();
+        let block_txids = txids(&BLOCK_100000_TXIDS);
+        let mut merkle_tree = MerkleTree::from_txids(&block_txids);
+        merkle_tree.build().unwrap();
+        for txid in &block_txids {
+            let proof = merkle_tree.audit_proof(txid).unwrap();
+            assert!(header.verify_audit_proof(txid, &proof));
+            assert_eq!(merkle_tree.merkle_branch(txid).ok(), MerkleBranch::from_proof(&proof, txid));
+        }
+        let mut odd_tree = MerkleTree::from_txids(&block_txids[..3]);
+        let odd_root = odd_tree.build().unwrap();
+        let proof = odd_tree.audit_proof(&block_txids[2]).unwrap();
+        let branch = MerkleBranch::from_proof(&proof, &block_txids[2]).unwrap();
+        assert_eq!(vec![block_txids[2], odd_tree.layers[1][0]], branch.hashes);
+        assert_eq!(odd_root, branch.root(&block_txids[2]));
+        assert!(!header.verify_audit_proof(&block_txids[2], &proof));
+
+        let mut rehash_proof = proof.clone();
+        rehash_proof.odd_node_policy = OddNodePolicy::Rehash;
+        assert_eq!(None, MerkleBranch::from_proof(&rehash_proof, &block_txids[2]));
+    }
+}
@@ -0,0 +1,192 @@
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use hash_function::hash_node;
+
+/// Доказательство согласованности (consistency proof) по RFC 6962: подтверждает, что дерево из
+/// `old_size` "листьев" является префиксом дерева из `new_size` "листьев", т.е. что история
+/// журнала между двумя опубликованными корнями не переписывалась.
+///
+/// Структура дерева при этом должна соответствовать RFC 6962, т.е. одиночный узел в конце слоя
+/// переносится на следующий слой без изменений (`OddNodePolicy::Promote`).
+#[derive(Clone)]
+pub struct ConsistencyProof<D: Hasher> {
+    /// Размер старого дерева.
+    pub old_size: usize,
+    /// Размер нового дерева.
+    pub new_size: usize,
+    /// Узлы доказательства в порядке, определённом RFC 6962.
+    pub path: Vec<D::Node>,
+    /// Хэш-функция дерева.
+    pub digest: D,
+    /// Режим хэширования узлов дерева.
+    pub mode: TreeMode,
+}
+
+impl_debug_eq!(ConsistencyProof { old_size, new_size, path, mode });
+
+impl<D: Hasher> ConsistencyProof<D> {
+    /// Проверяет, что дерево с корнем `old_root` является префиксом дерева с корнем `new_root`.
+    /// Для проверки не требуется само дерево.
+    pub fn verify(&self, old_root: &D::Node, new_root: &D::Node) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.path.is_empty() && old_root == new_root;
+        }
+        let mut path = self.path.iter();
+        let first = if self.old_size.is_power_of_two() {
+            *old_root
+        } else {
+            match path.next() {
+                Some(node) => *node,
+                None => return false,
+            }
+        };
+        let mut old_index = self.old_size - 1;
+        let mut new_index = self.new_size - 1;
+        while !old_index.is_multiple_of(2) {
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+        let mut old_hash = first;
+        let mut new_hash = first;
+        for node in path {
+            if new_index == 0 {
+                return false;
+            }
+            if !old_index.is_multiple_of(2) || old_index == new_index {
+                old_hash = hash_node(&self.digest, self.mode, node, &old_hash);
+                new_hash = hash_node(&self.digest, self.mode, node, &new_hash);
+                while old_index.is_multiple_of(2) && old_index != 0 {
+                    old_index >>= 1;
+                    new_index >>= 1;
+                }
+            } else {
+                new_hash = hash_node(&self.digest, self.mode, &new_hash, node);
+            }
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+        new_index == 0 && old_hash == *old_root && new_hash == *new_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate env_logger;
+    use super::ConsistencyProof;
+    use crypto::sha2::Sha256;
+    use merkle_tree::MerkleTree;
+    use odd_node_policy::OddNodePolicy;
+    use tree_mode::TreeMode;
+    use merkle_error::MerkleError;
+    use test_helpers::{RFC6962_LEAVES, hash_from_hex};
+
+    fn rfc6962_tree(size: usize) -> MerkleTree {
+        let mut merkle_tree = MerkleTree::rfc6962();
+        for leaf in &RFC6962_LEAVES[..size] {
+            merkle_tree.push_raw(leaf);
+        }
+        merkle_tree.build().unwrap();
+        merkle_tree
+    }
+
+    #[test]
+    fn rfc6962_test_vectors() {
+        let _ = env_logger::init();
+        let merkle_tree = rfc6962_tree(8);
+        let vectors: [(usize, usize, &[&str]); 3] = [
+            (1, 8, &["96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                     "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                     "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4"]),
+            (6, 8, &["0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                     "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                     "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7"]),
+            (2, 5, &["5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                     "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b"]),
+        ];
+        for &(old_size, new_size, path) in vectors.iter() {
+            let proof = merkle_tree.consistency_proof(old_size, new_size).unwrap();
+            let expected_path: Vec<[u8; 32]> = path.iter().map(|hex| hash_from_hex(hex)).collect();
+            assert_eq!(expected_path, proof.path);
+            let old_root = rfc6962_tree(old_size).get_merkle_root().unwrap();
+            let new_root = rfc6962_tree(new_size).get_merkle_root().unwrap();
+            assert!(proof.verify(&old_root, &new_root));
+        }
+    }
+
+    #[test]
+    fn verify_all_sizes() {
+        let _ = env_logger::init();
+        let merkle_tree = rfc6962_tree(8);
+        let roots: Vec<[u8; 32]> = (1..9)
+            .map(|size| rfc6962_tree(size).get_merkle_root().unwrap())
+            .collect();
+        for old_size in 1..9 {
+            for new_size in old_size..9 {
+                let proof = merkle_tree.consistency_proof(old_size, new_size).unwrap();
+                assert!(proof.verify(&roots[old_size - 1], &roots[new_size - 1]));
+                if old_size != new_size {
+                    assert!(!proof.verify(&roots[new_size - 1], &roots[new_size - 1]));
+                    let mut tampered = proof.clone();
+                    tampered.path[0][0] ^= 1;
+                    assert!(!tampered.verify(&roots[old_size - 1], &roots[new_size - 1]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detect_rewritten_history() {
+        let _ = env_logger::init();
+        let old_root = rfc6962_tree(3).get_merkle_root().unwrap();
+        let mut rewritten_tree = MerkleTree::rfc6962();
+        rewritten_tree.push_raw(RFC6962_LEAVES[0]);
+        rewritten_tree.push_raw(b"rewritten");
+        for leaf in &RFC6962_LEAVES[2..6] {
+            rewritten_tree.push_raw(leaf);
+        }
+        let new_root = rewritten_tree.build().unwrap();
+        let proof = rewritten_tree.consistency_proof(3, 6).unwrap();
+        assert!(!proof.verify(&old_root, &new_root));
+    }
+
+    #[test]
+    fn consistency_proof_failures() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::rfc6962();
+        merkle_tree.push_raw(RFC6962_LEAVES[0]);
+        assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.consistency_proof(1, 1));
+        merkle_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::InvalidTreeSizes { old_size: 0, new_size: 1 }),
+                   merkle_tree.consistency_proof(0, 1));
+        assert_eq!(Err(MerkleError::InvalidTreeSizes { old_size: 1, new_size: 2 }),
+                   merkle_tree.consistency_proof(1, 2));
+        let mut classic_tree = MerkleTree::default();
+        classic_tree.push_raw(RFC6962_LEAVES[0]);
+        classic_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::UnsupportedPolicy(OddNodePolicy::Rehash)),
+                   classic_tree.consistency_proof(1, 1));
+        let proof = ConsistencyProof {
+            old_size: 2,
+            new_size: 1,
+            path: vec![],
+            digest: Sha256::new(),
+            mode: TreeMode::Rfc6962,
+        };
+        let root = merkle_tree.get_merkle_root().unwrap();
+        assert!(!proof.verify(&root, &root));
+        let mut classic_promote = MerkleTree {
+            odd_node_policy: OddNodePolicy::Promote,
+            ..MerkleTree::default()
+        };
+        for leaf in &RFC6962_LEAVES[..5] {
+            classic_promote.push_raw(leaf);
+        }
+        let new_root = classic_promote.build().unwrap();
+        let proof = classic_promote.consistency_proof(5, 5).unwrap();
+        assert!(proof.verify(&new_root, &new_root));
+    }
+}
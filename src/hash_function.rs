@@ -1,19 +1,60 @@
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
+use hasher::{Hasher, Node};
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
 
-pub fn hash_leaf(value: &[u8]) -> [u8; 32] {
-    let mut sha = Sha256::new();
-    let mut result = [0; 32];
-    sha.input(&value);
-    sha.result(&mut result);
-    result
+/// Хэширует "лист" дерева переданной хэш-функцией.
+/// Экземпляр `digest` используется как прототип: он клонируется и сбрасывается перед хэшированием.
+pub fn hash_leaf<D>(digest: &D, mode: TreeMode, value: &[u8]) -> D::Node
+    where D: Hasher
+{
+    let mut hasher = digest.clone();
+    hasher.reset();
+    hasher.input(mode.leaf_prefix());
+    hasher.input(value);
+    finish(hasher)
+}
+
+/// Хэширует пару узлов дерева переданной хэш-функцией.
+pub fn hash_node<D>(digest: &D, mode: TreeMode, left: &D::Node, right: &D::Node) -> D::Node
+    where D: Hasher
+{
+    let mut hasher = digest.clone();
+    hasher.reset();
+    hasher.input(mode.node_prefix());
+    hasher.input(left.as_ref());
+    hasher.input(right.as_ref());
+    finish(hasher)
+}
+
+/// Вычисляет родителя для узла, которому не нашлось пары в конце слоя, согласно выбранному правилу.
+pub fn hash_lonely<D>(digest: &D, mode: TreeMode, policy: OddNodePolicy, node: &D::Node) -> D::Node
+    where D: Hasher
+{
+    match policy {
+        OddNodePolicy::Rehash => hash_leaf(digest, mode, node.as_ref()),
+        OddNodePolicy::Duplicate => hash_node(digest, mode, node, node),
+        OddNodePolicy::Promote => *node,
+        OddNodePolicy::ZeroPad => hash_node(digest, mode, node, &D::Node::zeroed()),
+    }
+}
+
+/// Вычисляет корень дерева, состоящего из единственного "листа".
+/// Только правило `Rehash` хэширует его повторно, в остальных случаях корнем является сам "лист".
+pub fn hash_single<D>(digest: &D, mode: TreeMode, policy: OddNodePolicy, leaf: &D::Node) -> D::Node
+    where D: Hasher
+{
+    match policy {
+        OddNodePolicy::Rehash => hash_lonely(digest, mode, policy, leaf),
+        _ => *leaf,
+    }
 }
 
-pub fn hash_node(left: &[u8], right: &[u8]) -> [u8; 32] {
-    let mut sha = Sha256::new();
-    let mut result = [0; 32];
-    sha.input(&left);
-    sha.input(&right);
-    sha.result(&mut result);
+/// Забирает результат хэширования в узел дерева.
+fn finish<D>(mut hasher: D) -> D::Node
+    where D: Hasher
+{
+    let mut result = D::Node::zeroed();
+    debug_assert_eq!(hasher.output_bytes(), result.as_ref().len());
+    hasher.result(result.as_mut());
     result
 }
@@ -0,0 +1,186 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use crypto::sha2::{Sha224, Sha256, Sha384, Sha512, Sha512Trunc224, Sha512Trunc256};
+use crypto::sha3::Sha3;
+use crypto::blake2b::Blake2b;
+use crypto::ripemd160::Ripemd160;
+use crypto::md5::Md5;
+
+/// Узел дерева - массив байт, размер которого совпадает с размером выхода хэш-функции.
+pub trait Node: AsRef<[u8]> + AsMut<[u8]> + Copy + Eq + Ord + Hash + Debug + Send + Sync + 'static {
+    /// Возвращает узел заполненный нулями.
+    fn zeroed() -> Self;
+}
+
+impl<const N: usize> Node for [u8; N] {
+    fn zeroed() -> [u8; N] {
+        [0; N]
+    }
+}
+
+/// Хэш-функция, пригодная для построения дерева.
+/// Связывает типаж `Digest` из rust-crypto с типом узла, размер которого равен размеру её выхода.
+pub trait Hasher: Digest + Clone + Send + Sync {
+    /// Тип узла дерева.
+    type Node: Node;
+}
+
+impl Hasher for Md5 {
+    type Node = [u8; 16];
+}
+
+impl Hasher for Sha1 {
+    type Node = [u8; 20];
+}
+
+impl Hasher for Ripemd160 {
+    type Node = [u8; 20];
+}
+
+impl Hasher for Sha224 {
+    type Node = [u8; 28];
+}
+
+impl Hasher for Sha256 {
+    type Node = [u8; 32];
+}
+
+impl Hasher for Sha384 {
+    type Node = [u8; 48];
+}
+
+impl Hasher for Sha512 {
+    type Node = [u8; 64];
+}
+
+impl Hasher for Sha512Trunc224 {
+    type Node = [u8; 28];
+}
+
+impl Hasher for Sha512Trunc256 {
+    type Node = [u8; 32];
+}
+
+/// Создаёт обёртку над хэш-функцией, размер выхода которой задаётся при создании экземпляра
+/// (SHA-3, Blake2b), фиксируя этот размер в типе.
+macro_rules! fixed_size_digest {
+    ($(#[$attr:meta])* $name:ident, $inner:ty, $init:expr, $size:expr) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy)]
+        pub struct $name($inner);
+
+        impl $name {
+            /// Создаёт новый экземпляр хэш-функции.
+            pub fn new() -> $name {
+                $name($init)
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> $name {
+                $name::new()
+            }
+        }
+
+        impl Digest for $name {
+            fn input(&mut self, input: &[u8]) {
+                self.0.input(input)
+            }
+
+            fn result(&mut self, out: &mut [u8]) {
+                self.0.result(out)
+            }
+
+            fn reset(&mut self) {
+                self.0.reset()
+            }
+
+            fn output_bits(&self) -> usize {
+                self.0.output_bits()
+            }
+
+            fn block_size(&self) -> usize {
+                self.0.block_size()
+            }
+        }
+
+        impl Hasher for $name {
+            type Node = [u8; $size];
+        }
+    }
+}
+
+fixed_size_digest!(
+    /// SHA3-224.
+    #[allow(non_camel_case_types)]
+    Sha3_224, Sha3, Sha3::sha3_224(), 28);
+fixed_size_digest!(
+    /// SHA3-256.
+    #[allow(non_camel_case_types)]
+    Sha3_256, Sha3, Sha3::sha3_256(), 32);
+fixed_size_digest!(
+    /// SHA3-384.
+    #[allow(non_camel_case_types)]
+    Sha3_384, Sha3, Sha3::sha3_384(), 48);
+fixed_size_digest!(
+    /// SHA3-512.
+    #[allow(non_camel_case_types)]
+    Sha3_512, Sha3, Sha3::sha3_512(), 64);
+fixed_size_digest!(
+    /// Keccak-256 - вариант SHA3-256 с исходным дополнением Keccak, используемый в Ethereum.
+    Keccak256, Sha3, Sha3::keccak256(), 32);
+fixed_size_digest!(
+    /// Blake2b с 256-битным выходом.
+    Blake2b256, Blake2b, Blake2b::new(32), 32);
+fixed_size_digest!(
+    /// Blake2b с 512-битным выходом.
+    Blake2b512, Blake2b, Blake2b::new(64), 64);
+
+/// Двойной SHA-256 - `SHA-256(SHA-256(data))`, используемый в Bitcoin.
+#[derive(Clone, Copy)]
+pub struct DoubleSha256(Sha256);
+
+impl DoubleSha256 {
+    /// Создаёт новый экземпляр хэш-функции.
+    pub fn new() -> DoubleSha256 {
+        DoubleSha256(Sha256::new())
+    }
+}
+
+impl Default for DoubleSha256 {
+    fn default() -> DoubleSha256 {
+        DoubleSha256::new()
+    }
+}
+
+impl Digest for DoubleSha256 {
+    fn input(&mut self, input: &[u8]) {
+        self.0.input(input)
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let mut first_round = [0; 32];
+        self.0.result(&mut first_round);
+        let mut second_round = Sha256::new();
+        second_round.input(&first_round);
+        second_round.result(out)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    fn output_bits(&self) -> usize {
+        self.0.output_bits()
+    }
+
+    fn block_size(&self) -> usize {
+        self.0.block_size()
+    }
+}
+
+impl Hasher for DoubleSha256 {
+    type Node = [u8; 32];
+}
@@ -9,17 +9,73 @@ extern crate log;
 extern crate crypto;
 extern crate bincode;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate hex_slice;
 extern crate serde_json;
 extern crate rmp_serde;
 extern crate rayon;
+extern crate libc;
 
+#[macro_use]
+mod macros;
 mod merkle_tree;
 mod serialization_format;
 // mod merkle_tree_string;
 mod hash_function;
 // mod hash_function_string;
+mod hasher;
+mod tree_mode;
+mod odd_node_policy;
+mod removal_policy;
+mod bitcoin;
+mod proof;
+mod consistency_proof;
+mod multi_proof;
+mod merkle_error;
+mod merkle_tree_builder;
+mod persistence;
+mod sparse_merkle_tree;
+mod sparse_merkle_proof;
+mod merkle_mountain_range;
+mod mountain_range_proof;
+mod sort_order;
+mod sorted_merkle_tree;
+mod sorted_proof;
+mod rlp;
+mod patricia_trie;
+mod patricia_proof;
+mod node_store;
+#[cfg(unix)]
+mod mmap_node_store;
+#[cfg(test)]
+mod test_helpers;
 
 pub use serialization_format::SerializationFormat;
-pub use merkle_tree::MerkleTree;
+pub use merkle_tree::{MerkleTree, DEFAULT_MIN_PARALLEL_LAYER_SIZE, DEFAULT_STORE_BATCH_SIZE};
+pub use persistence::TreeHeader;
+pub use node_store::{NodeStore, FileNodeStore};
+#[cfg(unix)]
+pub use mmap_node_store::MmapNodeStore;
+pub use merkle_error::MerkleError;
+pub use merkle_tree_builder::MerkleTreeBuilder;
+pub use tree_mode::TreeMode;
+pub use odd_node_policy::OddNodePolicy;
+pub use removal_policy::RemovalPolicy;
+pub use proof::{Position, Proof, ProofNode};
+pub use consistency_proof::ConsistencyProof;
+pub use multi_proof::MultiProof;
+pub use sparse_merkle_tree::{SparseMerkleTree, SparseKey, SPARSE_TREE_DEPTH};
+pub use sparse_merkle_proof::SparseMerkleProof;
+pub use merkle_mountain_range::MerkleMountainRange;
+pub use mountain_range_proof::MountainRangeProof;
+pub use sort_order::SortOrder;
+pub use sorted_merkle_tree::{SortedMerkleTree, SortedLeaf, sorted_root, empty_sorted_root};
+pub use sorted_proof::{SortedLeafProof, NonMembershipProof};
+pub use rlp::RlpItem;
+pub use patricia_trie::PatriciaTrie;
+pub use patricia_proof::{PatriciaProof, EthAccount};
+pub use hasher::{Hasher, Node, Sha3_224, Sha3_256, Sha3_384, Sha3_512, Blake2b256, Blake2b512,
+                 Keccak256, DoubleSha256};
+pub use bitcoin::{BlockHeader, MerkleBranch, bitcoin_hash_from_hex, bitcoin_hash_to_hex};
 // pub use merkle_tree_string::MerkleTreeString;
@@ -0,0 +1,22 @@
+/// Реализует `Debug`, `PartialEq` и `Eq` для доказательства с параметром `D: Hasher` по
+/// перечисленным полям. Поле `digest` не перечисляется: доказательства сравниваются по
+/// содержимому, экземпляр хэш-функции в сравнении и отладочном выводе не участвует.
+macro_rules! impl_debug_eq {
+    ($name:ident { $($field:ident),+ }) => {
+        impl<D: $crate::hasher::Hasher> ::std::fmt::Debug for $name<D> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    $(.field(stringify!($field), &self.$field))+
+                    .finish()
+            }
+        }
+
+        impl<D: $crate::hasher::Hasher> PartialEq for $name<D> {
+            fn eq(&self, other: &$name<D>) -> bool {
+                true $(&& self.$field == other.$field)+
+            }
+        }
+
+        impl<D: $crate::hasher::Hasher> Eq for $name<D> {}
+    }
+}
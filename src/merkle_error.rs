@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fmt;
+use odd_node_policy::OddNodePolicy;
+use tree_mode::TreeMode;
+use sort_order::SortOrder;
+
+/// Ошибки операций над деревом.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MerkleError {
+    /// В дереве нет ни одного "листа".
+    EmptyTree,
+    /// Дерево не было построено.
+    NotBuilt,
+    /// Хэш не найден в слое "листьев".
+    LeafNotFound,
+    /// Индекс "листа" выходит за пределы слоя "листьев" размером `len`.
+    IndexOutOfRange {
+        /// Запрошенный индекс.
+        index: usize,
+        /// Количество "листьев" в дереве.
+        len: usize,
+    },
+    /// Не передано ни одного индекса "листа".
+    NoLeafIndices,
+    /// Размеры деревьев для доказательства согласованности некорректны.
+    InvalidTreeSizes {
+        /// Размер старого дерева.
+        old_size: usize,
+        /// Размер нового дерева.
+        new_size: usize,
+    },
+    /// Операция не поддерживается для дерева с таким правилом обработки одиночных узлов.
+    UnsupportedPolicy(OddNodePolicy),
+    /// Операция не поддерживается для дерева с таким режимом хэширования узлов.
+    UnsupportedMode(TreeMode),
+    /// Операция не поддерживается для отсортированного дерева с таким порядком "листьев".
+    UnsupportedOrder(SortOrder),
+    /// "Лист" с таким ключом присутствует в дереве.
+    LeafExists,
+    /// Доказательство не сходится к переданному корню: узел отсутствует или его хэш не совпадает
+    /// со ссылкой родителя.
+    InvalidProof,
+    /// Узел дерева не совпадает с вычисленным из его потомков.
+    Corrupted {
+        /// Индекс слоя узла.
+        layer: usize,
+        /// Индекс узла в слое.
+        index: usize,
+    },
+    /// Значение не удалось сериализовать.
+    Serialization(String),
+    /// Сохранённое дерево не удалось прочитать: неверная сигнатура, версия формата или заголовок.
+    Deserialization(String),
+    /// Ошибка ввода-вывода при чтении или записи данных дерева.
+    Io(String),
+    /// Хранилище узлов доступно только для чтения.
+    ReadOnly,
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MerkleError::EmptyTree => write!(f, "No leaves in tree"),
+            MerkleError::NotBuilt => write!(f, "Tree not builded"),
+            MerkleError::LeafNotFound => write!(f, "Transaction hash not found in leaves layer"),
+            MerkleError::IndexOutOfRange { index, len } => {
+                write!(f, "Leaf index {} out of range for {} leaves", index, len)
+            }
+            MerkleError::NoLeafIndices => write!(f, "No leaf indices"),
+            MerkleError::InvalidTreeSizes { old_size, new_size } => {
+                write!(f, "Invalid tree sizes: {} -> {}", old_size, new_size)
+            }
+            MerkleError::UnsupportedPolicy(policy) => {
+                write!(f, "Operation is not supported for odd node policy {:?}", policy)
+            }
+            MerkleError::UnsupportedMode(mode) => {
+                write!(f, "Operation is not supported for tree mode {:?}", mode)
+            }
+            MerkleError::UnsupportedOrder(order) => {
+                write!(f, "Operation is not supported for sort order {:?}", order)
+            }
+            MerkleError::LeafExists => write!(f, "Key is present in leaves layer"),
+            MerkleError::InvalidProof => write!(f, "Proof does not match root hash"),
+            MerkleError::Corrupted { layer, index } => {
+                write!(f, "Tree invalidate at layer {} index {}", layer, index)
+            }
+            MerkleError::Serialization(ref message) => write!(f, "Serialization failed: {}", message),
+            MerkleError::Deserialization(ref message) => {
+                write!(f, "Deserialization failed: {}", message)
+            }
+            MerkleError::Io(ref message) => write!(f, "I/O error: {}", message),
+            MerkleError::ReadOnly => write!(f, "Node store is read-only"),
+        }
+    }
+}
+
+impl Error for MerkleError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::error::Error;
+    use super::MerkleError;
+    use merkle_tree::MerkleTree;
+
+    fn root_of(merkle_tree: &MerkleTree) -> Result<[u8; 32], Box<dyn Error>> {
+        Ok(merkle_tree.get_merkle_root()?)
+    }
+
+    #[test]
+    fn boxed_error() {
+        let error = root_of(&MerkleTree::default()).unwrap_err();
+        assert_eq!("No leaves in tree", error.to_string());
+        assert_eq!("Tree invalidate at layer 2 index 1",
+                   MerkleError::Corrupted { layer: 2, index: 1 }.to_string());
+    }
+}
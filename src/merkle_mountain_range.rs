@@ -0,0 +1,320 @@
+use std::fmt;
+use serde::Serialize;
+use crypto::sha2::Sha256;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use serialization_format::SerializationFormat;
+use hash_function::{hash_leaf, hash_node};
+use mountain_range_proof::MountainRangeProof;
+use merkle_error::MerkleError;
+
+/// Merkle Mountain Range - аккумулятор для журналов, в которые данные только добавляются.
+///
+/// "Листья" объединяются в совершенные поддеревья ("горы"): при добавлении "листа" сливаются
+/// только горы одинаковой высоты на правом краю, поэтому добавление занимает O(log n), а однажды
+/// записанные узлы никогда не меняются. Вершины гор ("пики") для `n` "листьев" соответствуют
+/// единичным битам `n`, корень получается их "упаковкой" (смотри `bag_peaks`). Так как узлы не
+/// переписываются, корни и доказательства включения доступны для любого прошлого размера.
+pub struct MerkleMountainRange<D: Hasher = Sha256> {
+    /// Формат сериализации транзакций перед тем как их хэшировать.
+    pub format: SerializationFormat,
+    /// Экземпляр хэш-функции, который клонируется при хэшировании каждого узла.
+    pub digest: D,
+    /// Режим хэширования узлов. Меняется только до добавления "листьев".
+    pub mode: TreeMode,
+    layers: Vec<Vec<D::Node>>,
+}
+
+impl Default for MerkleMountainRange<Sha256> {
+    fn default() -> MerkleMountainRange<Sha256> {
+        MerkleMountainRange::with_digest(Sha256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for MerkleMountainRange<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleMountainRange")
+            .field("format", &self.format)
+            .field("mode", &self.mode)
+            .field("layers", &self.layers)
+            .finish()
+    }
+}
+
+impl<D: Hasher> MerkleMountainRange<D> {
+    /// Создаёт пустой аккумулятор, использующий переданную хэш-функцию.
+    pub fn with_digest(digest: D) -> MerkleMountainRange<D> {
+        MerkleMountainRange {
+            format: SerializationFormat::MsgPack,
+            digest,
+            mode: TreeMode::Classic,
+            layers: vec![Vec::new()],
+        }
+    }
+
+    /// Количество добавленных "листьев".
+    pub fn len(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    /// Проверяет, были ли добавлены "листья".
+    pub fn is_empty(&self) -> bool {
+        self.layers[0].is_empty()
+    }
+
+    /// Сериализует транзакцию, хэширует её и добавляет как очередной "лист".
+    ///
+    /// # Failures
+    /// В случае, если транзакцию не удалось сериализовать возвращается `MerkleError::Serialization`.
+    pub fn push<Serializable>(&mut self, value: &Serializable) -> Result<(), MerkleError>
+        where Serializable: Serialize
+    {
+        let serialized_value = self.format.serialize(value)?;
+        self.push_raw(&serialized_value);
+        Ok(())
+    }
+
+    /// Добавляет как очередной "лист" хэш переданных байт без предварительной сериализации.
+    pub fn push_raw(&mut self, data: &[u8]) {
+        let hashed_data = hash_leaf(&self.digest, self.mode, data);
+        self.push_hash(hashed_data);
+    }
+
+    /// Добавляет как очередной "лист" уже вычисленный хэш и сливает получившиеся пары гор
+    /// одинаковой высоты.
+    pub fn push_hash(&mut self, hash: D::Node) {
+        self.layers[0].push(hash);
+        let mut height = 0;
+        while self.layers[height].len().is_multiple_of(2) {
+            let layer = &self.layers[height];
+            let parent = hash_node(&self.digest,
+                                   self.mode,
+                                   &layer[layer.len() - 2],
+                                   &layer[layer.len() - 1]);
+            if self.layers.len() == height + 1 {
+                self.layers.push(Vec::new());
+            }
+            self.layers[height + 1].push(parent);
+            height += 1;
+        }
+    }
+
+    /// Возвращает пики гор для аккумулятора из первых `size` "листьев" слева направо.
+    ///
+    /// # Failures
+    /// В случае, если `size` равен нулю возвращает `MerkleError::EmptyTree`, если превышает
+    /// количество "листьев" - `MerkleError::InvalidTreeSizes`.
+    pub fn peaks(&self, size: usize) -> Result<Vec<D::Node>, MerkleError> {
+        self.check_size(size)?;
+        Ok(peak_ranges(size)
+            .into_iter()
+            .map(|(height, start)| self.layers[height][start >> height])
+            .collect())
+    }
+
+    /// Корень аккумулятора из всех добавленных "листьев".
+    ///
+    /// # Failures
+    /// В случае, если "листья" не добавлялись возвращает `MerkleError::EmptyTree`.
+    pub fn root(&self) -> Result<D::Node, MerkleError> {
+        self.root_at(self.len())
+    }
+
+    /// Корень, который аккумулятор имел, когда в нём было `size` "листьев".
+    ///
+    /// # Failures
+    /// Смотри `MerkleMountainRange::peaks`.
+    pub fn root_at(&self, size: usize) -> Result<D::Node, MerkleError> {
+        let peaks = self.peaks(size)?;
+        Ok(bag_peaks(&self.digest, self.mode, &peaks))
+    }
+
+    /// Возвращает доказательство включения "листа" с индексом `leaf_index` в аккумулятор из
+    /// первых `size` "листьев", проверяемое относительно `root_at(size)`.
+    ///
+    /// # Failures
+    /// В случае, если `size` некорректен возвращает ошибки, описанные в
+    /// `MerkleMountainRange::peaks`, если "лист" не входит в первые `size` "листьев" -
+    /// `MerkleError::IndexOutOfRange`.
+    pub fn proof(&self, leaf_index: usize, size: usize) -> Result<MountainRangeProof<D>, MerkleError> {
+        self.check_size(size)?;
+        if leaf_index >= size {
+            return Err(MerkleError::IndexOutOfRange { index: leaf_index, len: size });
+        }
+        let mut path = Vec::new();
+        let mut peaks = Vec::new();
+        for (height, start) in peak_ranges(size) {
+            if leaf_index >= start && leaf_index < start + (1 << height) {
+                path = (0..height)
+                    .map(|level| self.layers[level][(leaf_index >> level) ^ 1])
+                    .collect();
+            } else {
+                peaks.push(self.layers[height][start >> height]);
+            }
+        }
+        Ok(MountainRangeProof {
+            leaf_index,
+            size,
+            path,
+            peaks,
+            digest: self.digest.clone(),
+            mode: self.mode,
+        })
+    }
+
+    /// Проверяет, что `size` не равен нулю и не превышает количество "листьев".
+    fn check_size(&self, size: usize) -> Result<(), MerkleError> {
+        if size == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if size > self.len() {
+            return Err(MerkleError::InvalidTreeSizes { old_size: size, new_size: self.len() });
+        }
+        Ok(())
+    }
+}
+
+/// Возвращает высоты и индексы первых "листьев" гор аккумулятора из `size` "листьев" слева
+/// направо: каждому единичному биту `size`, начиная со старшего, соответствует одна гора.
+pub fn peak_ranges(size: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for height in (0..usize::BITS as usize).rev() {
+        if size & (1 << height) != 0 {
+            ranges.push((height, start));
+            start += 1 << height;
+        }
+    }
+    ranges
+}
+
+/// "Упаковывает" пики в корень справа налево: правый пик объединяется с соседним слева, результат -
+/// со следующим и т.д. Корень аккумулятора из одной горы совпадает с её пиком.
+pub fn bag_peaks<D>(digest: &D, mode: TreeMode, peaks: &[D::Node]) -> D::Node
+    where D: Hasher
+{
+    let (last, rest) = peaks.split_last().expect("No peaks to bag");
+    rest.iter().rev().fold(*last, |bagged, peak| hash_node(digest, mode, peak, &bagged))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use super::MerkleMountainRange;
+    use mountain_range_proof::MountainRangeProof;
+    use merkle_tree::MerkleTree;
+    use odd_node_policy::OddNodePolicy;
+    use serialization_format::SerializationFormat;
+    use merkle_error::MerkleError;
+
+    fn build_range(size: usize) -> MerkleMountainRange {
+        let mut mountain_range = MerkleMountainRange::default();
+        for leaf in 0..size {
+            mountain_range.push(&leaf).unwrap();
+        }
+        mountain_range
+    }
+
+    #[test]
+    fn peaks_follow_size_bits() {
+        let _ = env_logger::init();
+        let mountain_range = build_range(11);
+        assert_eq!(3, mountain_range.peaks(11).unwrap().len());
+        assert_eq!(1, mountain_range.peaks(8).unwrap().len());
+        assert_eq!(Err(MerkleError::EmptyTree), mountain_range.peaks(0));
+        assert_eq!(Err(MerkleError::InvalidTreeSizes { old_size: 12, new_size: 11 }),
+                   mountain_range.peaks(12));
+        assert_eq!(Err(MerkleError::EmptyTree), MerkleMountainRange::default().root());
+    }
+
+    #[test]
+    fn single_mountain_matches_merkle_tree() {
+        let _ = env_logger::init();
+        for size in &[1usize, 2, 4, 8, 16] {
+            let leaves: Vec<usize> = (0..*size).collect();
+            let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::MsgPack).unwrap();
+            merkle_tree.odd_node_policy = OddNodePolicy::Promote;
+            assert_eq!(merkle_tree.build(), build_range(*size).root());
+        }
+    }
+
+    #[test]
+    fn historical_roots() {
+        let _ = env_logger::init();
+        let mountain_range = build_range(20);
+        for size in 1..21 {
+            assert_eq!(build_range(size).root(), mountain_range.root_at(size));
+        }
+    }
+
+    #[test]
+    fn proofs_against_historical_sizes() {
+        let _ = env_logger::init();
+        let mountain_range = build_range(20);
+        for size in 1..21 {
+            let root = mountain_range.root_at(size).unwrap();
+            for leaf in 0..size {
+                let proof = mountain_range.proof(leaf, size).unwrap();
+                assert!(proof.verify_value(&root, size, &leaf, &SerializationFormat::MsgPack));
+                assert!(!proof.verify_value(&root, size, &(leaf + 1), &SerializationFormat::MsgPack));
+                if size < 20 {
+                    assert!(!proof.verify_value(&mountain_range.root().unwrap(),
+                                                20,
+                                                &leaf,
+                                                &SerializationFormat::MsgPack));
+                }
+            }
+            assert_eq!(Err(MerkleError::IndexOutOfRange { index: size, len: size }),
+                       mountain_range.proof(size, size));
+        }
+    }
+
+    #[test]
+    fn reject_tampered_proof() {
+        let _ = env_logger::init();
+        let mountain_range = build_range(11);
+        let root = mountain_range.root().unwrap();
+        let proof = mountain_range.proof(9, 11).unwrap();
+        assert!(proof.verify_value(&root, 11, &9usize, &SerializationFormat::MsgPack));
+
+        let mut tampered_path = proof.clone();
+        tampered_path.path[0][0] ^= 1;
+        assert!(!tampered_path.verify_value(&root, 11, &9usize, &SerializationFormat::MsgPack));
+
+        let mut missing_peak = proof.clone();
+        missing_peak.peaks.pop();
+        assert!(!missing_peak.verify_value(&root, 11, &9usize, &SerializationFormat::MsgPack));
+
+        let mut wrong_size = proof.clone();
+        wrong_size.size = 10;
+        assert!(!wrong_size.verify_value(&root, 11, &9usize, &SerializationFormat::MsgPack));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.leaf_index = 8;
+        assert!(!wrong_index.verify_value(&root, 11, &9usize, &SerializationFormat::MsgPack));
+    }
+
+    #[test]
+    fn reject_forged_size() {
+        let _ = env_logger::init();
+        let mountain_range = build_range(3);
+        let root = mountain_range.root().unwrap();
+        let peaks = mountain_range.peaks(3).unwrap();
+        let proof = mountain_range.proof(2, 3).unwrap();
+        let leaf_hash = mountain_range.layers[0][2];
+        assert!(proof.verify(&root, 3, &leaf_hash));
+
+        let forged = MountainRangeProof {
+            leaf_index: 1,
+            size: 2,
+            path: vec![peaks[0]],
+            peaks: Vec::new(),
+            digest: proof.digest,
+            mode: proof.mode,
+        };
+        assert_eq!(Some(root), forged.root(&leaf_hash));
+        assert!(!forged.verify(&root, 3, &leaf_hash));
+        assert!(!proof.verify(&root, 2, &leaf_hash));
+    }
+}
@@ -1,50 +1,161 @@
 use serde::Serialize;
 use std::default::Default;
+use std::fmt;
+use std::sync::Arc;
+use crypto::sha2::Sha256;
 use serialization_format::SerializationFormat;
 use rayon::prelude::*;
-use hash_function::{hash_leaf, hash_node};
+use rayon::ThreadPool;
+use hash_function::{hash_leaf, hash_node, hash_lonely, hash_single};
+use hasher::{Hasher, Node};
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
+use removal_policy::RemovalPolicy;
+use proof::{Position, Proof, ProofNode};
+use consistency_proof::ConsistencyProof;
+use multi_proof::MultiProof;
+use merkle_error::MerkleError;
+use node_store::NodeStore;
 
+/// Минимальный размер слоя по умолчанию, начиная с которого слой обрабатывается многопоточно.
+/// На меньших слоях накладные расходы на распределение работы между потоками превышают выигрыш.
+pub const DEFAULT_MIN_PARALLEL_LAYER_SIZE: usize = 1024;
+
+/// Количество узлов по умолчанию, вычисляемых за один проход при построении дерева в хранилище
+/// методом `build_store`. Ограничивает объём памяти, занимаемый дочерними и родительскими узлами.
+pub const DEFAULT_STORE_BATCH_SIZE: usize = 64 * 1024;
 
 /// Структура хранящая и отвечающая за Merkle Tree.
-#[derive(Debug, Eq, PartialEq)]
-pub struct MerkleTree {
+/// Параметризуется хэш-функцией, реализующей типаж `Hasher` (по умолчанию SHA-256).
+/// Размер узлов дерева совпадает с размером выхода хэш-функции.
+/// Узлы хранятся в хранилище `S`, по умолчанию - в памяти, в списке слоёв. Деревья с другими
+/// хранилищами (смотри `NodeStore`) строятся и обслуживаются методами `with_store`,
+/// `append_leaves`, `build_store`, `get_merkle_root` и `proof_by_index`.
+pub struct MerkleTree<D: Hasher = Sha256, S = Vec<Vec<<D as Hasher>::Node>>> {
     /// Список слоёв дерева. Включая 0-ой слой с "листьями" (хэшами транзакций).
-    pub layers: Vec<Vec<[u8; 32]>>,
+    pub layers: S,
     /// Формат сериализации транзакций перед тем как их хэшировать.
     pub format: SerializationFormat,
     /// Флаг отвечающий за параллелизацию при построении новых слоёв дерева.
     pub parallel: bool,
+    /// Минимальный размер слоя, начиная с которого он обрабатывается многопоточно при
+    /// установленном флаге `parallel`. Слои меньшего размера обрабатываются в одном потоке.
+    pub min_parallel_layer_size: usize,
+    /// Количество родительских узлов, вычисляемых за один проход в `build_store`.
+    pub store_batch_size: usize,
+    /// Пул потоков rayon, в котором выполняется многопоточная обработка. Если не задан -
+    /// используется глобальный пул.
+    pub thread_pool: Option<Arc<ThreadPool>>,
     /// Флаг указывающий на состояние дерева. Было оно построено или нет.
     pub builded: bool,
+    /// Экземпляр хэш-функции, который клонируется при хэшировании каждого узла.
+    pub digest: D,
+    /// Режим хэширования узлов. Меняется только до добавления "листьев" в дерево.
+    pub mode: TreeMode,
+    /// Правило обработки узла, которому не нашлось пары в конце слоя.
+    pub odd_node_policy: OddNodePolicy,
+    /// Правило удаления "листа" методом `remove`.
+    pub removal_policy: RemovalPolicy,
+}
+
+impl Default for MerkleTree<Sha256> {
+    fn default() -> MerkleTree<Sha256> {
+        MerkleTree::with_digest(Sha256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for MerkleTree<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("layers", &self.layers)
+            .field("format", &self.format)
+            .field("parallel", &self.parallel)
+            .field("min_parallel_layer_size", &self.min_parallel_layer_size)
+            .field("store_batch_size", &self.store_batch_size)
+            .field("thread_pool", &self.thread_pool.is_some())
+            .field("builded", &self.builded)
+            .field("mode", &self.mode)
+            .field("odd_node_policy", &self.odd_node_policy)
+            .field("removal_policy", &self.removal_policy)
+            .finish()
+    }
+}
+
+/// Деревья сравниваются по содержимому, экземпляр хэш-функции, пул потоков и размер прохода
+/// `build_store` в сравнении не участвуют.
+impl<D: Hasher> PartialEq for MerkleTree<D> {
+    fn eq(&self, other: &MerkleTree<D>) -> bool {
+        self.layers == other.layers && self.format == other.format &&
+        self.parallel == other.parallel &&
+        self.min_parallel_layer_size == other.min_parallel_layer_size &&
+        self.builded == other.builded &&
+        self.mode == other.mode && self.odd_node_policy == other.odd_node_policy &&
+        self.removal_policy == other.removal_policy
+    }
+}
+
+impl<D: Hasher> Eq for MerkleTree<D> {}
+
+impl MerkleTree<Sha256> {
+    /// Производит создание "основы" Merkle tree с хэш-функцией SHA-256.
+    /// Смотри `MerkleTree::from_with_digest`.
+    pub fn from<Serializable>(leaves: &[Serializable],
+                              format: SerializationFormat)
+                              -> Result<MerkleTree<Sha256>, MerkleError>
+        where Serializable: Serialize + Clone + Sync
+    {
+        MerkleTree::from_with_digest(leaves, format, Sha256::new())
+    }
+
+    /// Создаёт пустое дерево совместимое с RFC 6962 (Certificate Transparency).
+    /// Для совпадения корня с логами CT "листья" следует добавлять через `push_raw`.
+    pub fn rfc6962() -> MerkleTree<Sha256> {
+        MerkleTree {
+            mode: TreeMode::Rfc6962,
+            odd_node_policy: OddNodePolicy::Promote,
+            ..MerkleTree::default()
+        }
+    }
 }
 
-impl Default for MerkleTree {
-    fn default() -> MerkleTree {
+impl<D> MerkleTree<D>
+    where D: Hasher
+{
+    /// Создаёт пустое дерево, использующее переданную хэш-функцию.
+    pub fn with_digest(digest: D) -> MerkleTree<D> {
         let mut layers = Vec::with_capacity(256);
         layers.push(Vec::with_capacity(512));
         MerkleTree {
-            layers: layers,
+            layers,
             parallel: true,
+            min_parallel_layer_size: DEFAULT_MIN_PARALLEL_LAYER_SIZE,
+            store_batch_size: DEFAULT_STORE_BATCH_SIZE,
+            thread_pool: None,
             builded: false,
             format: SerializationFormat::MsgPack,
+            digest,
+            mode: TreeMode::Classic,
+            odd_node_policy: OddNodePolicy::Rehash,
+            removal_policy: RemovalPolicy::Tombstone,
         }
     }
-}
 
-impl MerkleTree {
     /// Производит построение дерева основываяся на 0-ом слое "листьев".
     /// Возвращает Merkle Root Hash.
     ///
     /// # Failures
-    /// В случае, если 0-ой слой "листьев" пуст возвращает Err - "No leaves in tree".
-    pub fn build(&mut self) -> Result<[u8;32], &str> {
+    /// В случае, если 0-ой слой "листьев" пуст возвращает `MerkleError::EmptyTree`.
+    pub fn build(&mut self) -> Result<D::Node, MerkleError> {
         match self.layers[0].len() {
             0 => {
-               Err("No leaves in tree")
+               Err(MerkleError::EmptyTree)
             }
             1 => {
-                debug!("Tree have one leaf. Merke root hash == hash(leaf[0])");
-                let hashed_leaf = hash_leaf(&self.layers[0][0]);
+                debug!("Tree have one leaf. Merke root hash is defined by odd node policy");
+                let hashed_leaf = hash_single(&self.digest,
+                                              self.mode,
+                                              self.odd_node_policy,
+                                              &self.layers[0][0]);
                 debug!("Layers len: {}", self.layers.len());
                 self.layers.push(Vec::with_capacity(1250));
                 self.layers[1].push(hashed_leaf);
@@ -53,70 +164,84 @@ impl MerkleTree {
             }
             _ => {
                 debug!("Tree have more than one leaf.");
-                self.recursive_create_nodes(0);
+                match self.thread_pool.clone() {
+                    Some(thread_pool) => thread_pool.install(|| self.recursive_create_nodes(0)),
+                    None => self.recursive_create_nodes(0),
+                }
                 self.builded = true;
                 self.get_merkle_root()
             }
         }
     }
 
-    /// Возвращает merkle root hash в качестве 32-байтного массива.
-    pub fn get_merkle_root(&self) -> Result<[u8; 32], &str> {
-        match self.layers.last() {
-            Some(root_layer) => {
-                match root_layer.last() {
-                    Some(merkle_root_hash) => {
-                        Ok(merkle_root_hash.clone())
-                    },
-                    None => {
-                        Err("Root hash not found")
-                    }
-                }
-            },
-            None => {
-                Err("Tree is empty")
-            }
-        }
-    }
-
     /// Производит создание "основы" Merkle tree.
     /// Принимает входной слайс транзакций, сериализует их, хэширует и добавляет в нулевой уровень.
     /// Так же заранее выделяет слои для будущего заполнения дерева резервируя чуть больше места чем нужно.
-    pub fn from<Serializable>(leaves: &[Serializable], format: SerializationFormat) -> MerkleTree
-        where Serializable: Serialize + Clone
+    /// Хэширование производится переданной хэш-функцией многопоточно.
+    /// Смотри `MerkleTree::from_with_parallel`.
+    ///
+    /// # Failures
+    /// В случае, если какую-либо из транзакций не удалось сериализовать возвращается
+    /// `MerkleError::Serialization`.
+    pub fn from_with_digest<Serializable>(leaves: &[Serializable],
+                                          format: SerializationFormat,
+                                          digest: D)
+                                          -> Result<MerkleTree<D>, MerkleError>
+        where Serializable: Serialize + Clone + Sync
+    {
+        MerkleTree::from_with_parallel(leaves, format, digest, true)
+    }
+
+    /// Производит создание "основы" Merkle tree, как `MerkleTree::from_with_digest`, позволяя
+    /// выбрать режим работы дерева при создании. Значение `parallel` определяет, будут ли
+    /// сериализация и хэширование "листьев", а затем и построение слоёв, выполняться многопоточно.
+    /// "Листья" добавляются через `MerkleTree::extend` с настройками по умолчанию; чтобы задать
+    /// `min_parallel_layer_size` или `thread_pool` до хэширования "листьев", следует создать дерево
+    /// через `MerkleTree::with_digest`, изменить настройки и вызвать `extend`.
+    ///
+    /// # Failures
+    /// В случае, если какую-либо из транзакций не удалось сериализовать возвращается
+    /// `MerkleError::Serialization`.
+    pub fn from_with_parallel<Serializable>(leaves: &[Serializable],
+                                            format: SerializationFormat,
+                                            digest: D,
+                                            parallel: bool)
+                                            -> Result<MerkleTree<D>, MerkleError>
+        where Serializable: Serialize + Clone + Sync
     {
         let log2_leaves = (leaves.len() as f64).log2();
-        println!("log2 leaves: {}", log2_leaves);
-        let mut base_layer : Vec<[u8;32]> = leaves.iter()
-            .map(|element| {
-                let serialized_element = format.serialize(&element);
-                hash_leaf(&serialized_element)
-            })
-            .collect();
-        let mut layer_len = base_layer.len();
-        base_layer.reserve(layer_len);
-        let mut layers: Vec<Vec<[u8; 32]>> = Vec::with_capacity(((log2_leaves).ceil()) as usize);
-        layers.push(base_layer);
-        while layer_len != 1 {
+        debug!("log2 leaves: {}", log2_leaves);
+        let mut layer_len = leaves.len();
+        let mut layers: Vec<Vec<D::Node>> = Vec::with_capacity(((log2_leaves).ceil()) as usize);
+        layers.push(Vec::with_capacity(layer_len * 2));
+        while layer_len > 1 {
             debug!("Create new layer with capacity: {}", layer_len);
             layers.push(Vec::with_capacity(layer_len));
-            layer_len = layer_len / 2;
+            layer_len /= 2;
         }
-        MerkleTree {
-            layers: layers,
-            parallel: true,
+        let mut merkle_tree = MerkleTree {
+            layers,
+            parallel,
+            min_parallel_layer_size: DEFAULT_MIN_PARALLEL_LAYER_SIZE,
+            store_batch_size: DEFAULT_STORE_BATCH_SIZE,
+            thread_pool: None,
             builded: false,
-            format: format,
-        }
-
+            format,
+            digest,
+            mode: TreeMode::Classic,
+            odd_node_policy: OddNodePolicy::Rehash,
+            removal_policy: RemovalPolicy::Tombstone,
+        };
+        merkle_tree.extend(leaves)?;
+        Ok(merkle_tree)
     }
 
     /// Рекурсивно создаёт слои дерева поднимаясь вверх.
-    /// В зависимости от флага `parallel` делает это многопоточно, либо в одном потоке.
+    /// В зависимости от флага `parallel` и размера слоя делает это многопоточно, либо в одном потоке.
     fn recursive_create_nodes(&mut self, current_layer_index: usize) {
         debug!("Layer index: {}", current_layer_index);
         if self.layers[current_layer_index].len() > 1 {
-            if self.parallel {
+            if self.use_parallel(self.layers[current_layer_index].len()) {
                 self.generate_new_layer_parallel(current_layer_index);
             } else {
                 self.generate_new_layer_sequence(current_layer_index);
@@ -127,32 +252,40 @@ impl MerkleTree {
 
     /// Генерирует новый слой дерева параллельно.
     fn generate_new_layer_parallel(&mut self, current_layer_index: usize) {
-        let mut new_layer = self.layers[current_layer_index]
-            .par_chunks(2)
-            .map(|pair| {
-                if pair.len() == 2 {
-                    hash_node(&pair[0], &pair[1])
-                } else {
-                    hash_leaf(&pair[0])
-                }
-            })
-            .collect::<Vec<[u8; 32]>>();
+        let digest = &self.digest;
+        let mode = self.mode;
+        let policy = self.odd_node_policy;
+        let layer = &self.layers[current_layer_index];
+        let mut new_layer = self.install(|| {
+            layer.par_chunks(2)
+                .map(|pair| {
+                    if pair.len() == 2 {
+                        hash_node(digest, mode, &pair[0], &pair[1])
+                    } else {
+                        hash_lonely(digest, mode, policy, &pair[0])
+                    }
+                })
+                .collect::<Vec<D::Node>>()
+        });
         let current_layer_len = self.layers[current_layer_index].len();
         self.create_new_layer(current_layer_index, current_layer_len, &mut new_layer);
     }
 
     /// Генерирует новый слой дерева однопоточно.
     fn generate_new_layer_sequence(&mut self, current_layer_index: usize) {
+        let digest = &self.digest;
+        let mode = self.mode;
+        let policy = self.odd_node_policy;
         let mut new_layer = self.layers[current_layer_index]
             .chunks(2)
             .map(|pair| {
                 if pair.len() == 2 {
-                    hash_node(&pair[0], &pair[1])
+                    hash_node(digest, mode, &pair[0], &pair[1])
                 } else {
-                    hash_leaf(&pair[0])
+                    hash_lonely(digest, mode, policy, &pair[0])
                 }
             })
-            .collect::<Vec<[u8; 32]>>();
+            .collect::<Vec<D::Node>>();
         self.create_new_layer(current_layer_index, new_layer.len() * 2, &mut new_layer);
     }
 
@@ -161,31 +294,272 @@ impl MerkleTree {
     fn create_new_layer(&mut self,
                         current_layer_index: usize,
                         new_layer_base_capacity: usize,
-                        mut new_layer: &mut Vec<[u8; 32]>) {
+                        new_layer: &mut Vec<D::Node>) {
         let layers_last_index = self.layers.len() - 1;
-        if layers_last_index >= current_layer_index + 1 {
-            self.layers[current_layer_index + 1].append(&mut new_layer);
+        if layers_last_index > current_layer_index {
+            self.layers[current_layer_index + 1].append(new_layer);
         } else {
             self.layers.push(Vec::with_capacity(new_layer_base_capacity));
-            self.layers[current_layer_index + 1].append(&mut new_layer);
+            self.layers[current_layer_index + 1].append(new_layer);
         }
     }
 
     /// Добавляет хэш сериализованной транзакции в слой "листьев" (0-ой слой).
     /// Если дерево было до этого построено - вызывает функцию пересчёта узлов дерева.
-    pub fn push<Serializable>(&mut self, other: &Serializable)
+    ///
+    /// # Failures
+    /// В случае, если транзакцию не удалось сериализовать возвращается `MerkleError::Serialization`,
+    /// дерево при этом не изменяется.
+    pub fn push<Serializable>(&mut self, other: &Serializable) -> Result<(), MerkleError>
         where Serializable: Serialize
     {
+        let serialized_other = self.format.serialize(&other)?;
+        self.push_raw(&serialized_other);
+        Ok(())
+    }
+
+    /// Добавляет в слой "листьев" хэш переданных байт без предварительной сериализации.
+    /// Если дерево было до этого построено - вызывает функцию пересчёта узлов дерева.
+    pub fn push_raw(&mut self, data: &[u8]) {
+        let hashed_data = hash_leaf(&self.digest, self.mode, data);
+        self.push_hash(hashed_data);
+    }
+
+    /// Добавляет в слой "листьев" уже вычисленный хэш (например, идентификатор транзакции).
+    /// Если дерево было до этого построено - вызывает функцию пересчёта узлов дерева.
+    pub fn push_hash(&mut self, hash: D::Node) {
         debug!("ADD NEW LEAF");
+        self.layers[0].push(hash);
         if self.builded {
-            let serialized_other = self.format.serialize(&other);
-            let hashed_other = hash_leaf(&serialized_other);
-            self.layers[0].push(hashed_other);
             self.recursive_repair_branch(0);
+        }
+    }
+
+    /// Добавляет в слой "листьев" хэши сразу нескольких сериализованных транзакций.
+    /// Если дерево было до этого построено - пересчитывает каждый затронутый слой один раз,
+    /// а не правый край дерева после каждого "листа", как при последовательных вызовах `push`.
+    /// При установленном флаге `parallel` сериализация, хэширование и пересчёт слоёв выполняются
+    /// многопоточно.
+    ///
+    /// # Failures
+    /// В случае, если какую-либо из транзакций не удалось сериализовать возвращается
+    /// `MerkleError::Serialization`, дерево при этом не изменяется.
+    pub fn extend<Serializable>(&mut self, values: &[Serializable]) -> Result<(), MerkleError>
+        where Serializable: Serialize + Sync
+    {
+        let digest = &self.digest;
+        let mode = self.mode;
+        let format = &self.format;
+        let hash_value = |value: &Serializable| {
+            let serialized_value = format.serialize(value)?;
+            Ok(hash_leaf(digest, mode, &serialized_value))
+        };
+        let hashes = if self.use_parallel(values.len()) {
+            self.install(|| values.par_iter().map(&hash_value).collect::<Vec<Result<D::Node, MerkleError>>>())
+                .into_iter()
+                .collect::<Result<Vec<D::Node>, MerkleError>>()?
         } else {
-            let serialized_other = self.format.serialize(&other);
-            let hashed_other = hash_leaf(&serialized_other);
-            self.layers[0].push(hashed_other);
+            values.iter().map(hash_value).collect::<Result<Vec<D::Node>, MerkleError>>()?
+        };
+        self.push_batch(&hashes);
+        Ok(())
+    }
+
+    /// Добавляет в слой "листьев" сразу несколько уже вычисленных хэшей.
+    /// Смотри `MerkleTree::extend`.
+    pub fn push_batch(&mut self, hashes: &[D::Node]) {
+        if hashes.is_empty() {
+            return;
+        }
+        let old_len = self.layers[0].len();
+        self.layers[0].extend_from_slice(hashes);
+        if self.builded {
+            self.rehash_from(old_len);
+        }
+    }
+
+    /// Заменяет значение "листа" с переданным индексом в построенном дереве и пересчитывает только
+    /// узлы на пути от него к корню. Возвращает новый merkle root hash.
+    ///
+    /// # Failures
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// В случае, если индекс выходит за пределы слоя "листьев" - `MerkleError::IndexOutOfRange`.
+    /// В случае, если значение не удалось сериализовать - `MerkleError::Serialization`.
+    /// При ошибке дерево не изменяется.
+    pub fn update<Serializable>(&mut self, index: usize, value: &Serializable) -> Result<D::Node, MerkleError>
+        where Serializable: Serialize
+    {
+        self.check_leaf_index(index)?;
+        let serialized_value = self.format.serialize(value)?;
+        self.update_raw(index, &serialized_value)
+    }
+
+    /// Заменяет "лист" с переданным индексом хэшем переданных байт без предварительной сериализации.
+    /// Смотри `MerkleTree::update`.
+    pub fn update_raw(&mut self, index: usize, data: &[u8]) -> Result<D::Node, MerkleError> {
+        let hashed_data = hash_leaf(&self.digest, self.mode, data);
+        self.update_hash(index, hashed_data)
+    }
+
+    /// Заменяет "лист" с переданным индексом уже вычисленным хэшем.
+    /// Смотри `MerkleTree::update`.
+    pub fn update_hash(&mut self, index: usize, hash: D::Node) -> Result<D::Node, MerkleError> {
+        self.check_leaf_index(index)?;
+        self.layers[0][index] = hash;
+        self.recompute_path(index);
+        self.get_merkle_root()
+    }
+
+    /// Проверяет, что дерево построено и индекс не выходит за пределы слоя "листьев".
+    fn check_leaf_index(&self, index: usize) -> Result<(), MerkleError> {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        if index >= self.layers[0].len() {
+            return Err(MerkleError::IndexOutOfRange { index, len: self.layers[0].len() });
+        }
+        Ok(())
+    }
+
+    /// Пересчитывает узлы на пути от "листа" с переданным индексом до корня построенного дерева.
+    fn recompute_path(&mut self, leaf_index: usize) {
+        if self.layers[0].len() == 1 {
+            self.layers[1][0] = hash_single(&self.digest,
+                                            self.mode,
+                                            self.odd_node_policy,
+                                            &self.layers[0][0]);
+            return;
+        }
+        let mut index = leaf_index;
+        let mut layer_index = 0;
+        while self.layers[layer_index].len() > 1 {
+            let layer = &self.layers[layer_index];
+            let left_index = index - index % 2;
+            let new_node = if left_index + 1 < layer.len() {
+                hash_node(&self.digest, self.mode, &layer[left_index], &layer[left_index + 1])
+            } else {
+                hash_lonely(&self.digest, self.mode, self.odd_node_policy, &layer[left_index])
+            };
+            index /= 2;
+            self.layers[layer_index + 1][index] = new_node;
+            layer_index += 1;
+        }
+    }
+
+    /// Оставляет в дереве только первые `new_len` "листьев" (например, чтобы откатить последние
+    /// добавленные записи). В построенном дереве пересчитывается только правый край каждого слоя,
+    /// а ставшие лишними верхние слои удаляются. Дерево, из которого удалены все "листья",
+    /// становится пустым и непостроенным.
+    ///
+    /// # Failures
+    /// В случае, если `new_len` больше количества "листьев" возвращается
+    /// `MerkleError::IndexOutOfRange`, дерево при этом не изменяется.
+    pub fn truncate(&mut self, new_len: usize) -> Result<(), MerkleError> {
+        if new_len > self.layers[0].len() {
+            return Err(MerkleError::IndexOutOfRange { index: new_len, len: self.layers[0].len() });
+        }
+        self.layers[0].truncate(new_len);
+        if self.builded {
+            self.rehash_from(new_len);
+        }
+        Ok(())
+    }
+
+    /// Удаляет "лист" с переданным индексом согласно правилу `removal_policy`.
+    ///
+    /// # Failures
+    /// В случае, если индекс выходит за пределы слоя "листьев" возвращается
+    /// `MerkleError::IndexOutOfRange`, дерево при этом не изменяется.
+    pub fn remove(&mut self, index: usize) -> Result<(), MerkleError> {
+        if index >= self.layers[0].len() {
+            return Err(MerkleError::IndexOutOfRange { index, len: self.layers[0].len() });
+        }
+        match self.removal_policy {
+            RemovalPolicy::Tombstone => {
+                self.layers[0][index] = D::Node::zeroed();
+                if self.builded {
+                    self.recompute_path(index);
+                }
+            }
+            RemovalPolicy::Shift => {
+                self.layers[0].remove(index);
+                if self.builded {
+                    self.rehash_from(index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Вставляет уже вычисленный хэш "листа" в позицию `index`, сдвигая последующие "листья" вправо.
+    /// В построенном дереве пересчитываются только узлы, зависящие от "листьев" начиная с `index`.
+    ///
+    /// # Failures
+    /// В случае, если индекс больше количества "листьев" возвращается
+    /// `MerkleError::IndexOutOfRange`, дерево при этом не изменяется.
+    pub fn insert_hash(&mut self, index: usize, hash: D::Node) -> Result<(), MerkleError> {
+        if index > self.layers[0].len() {
+            return Err(MerkleError::IndexOutOfRange { index, len: self.layers[0].len() });
+        }
+        self.layers[0].insert(index, hash);
+        if self.builded {
+            self.rehash_from(index);
+        }
+        Ok(())
+    }
+
+    /// Пересчитывает в построенном дереве все узлы, зависящие от "листьев" начиная с `leaf_index`,
+    /// после того как слой "листьев" был изменён или укорочен. Лишние узлы и слои удаляются,
+    /// недостающие добавляются.
+    fn rehash_from(&mut self, leaf_index: usize) {
+        match self.layers[0].len() {
+            0 => {
+                self.layers.truncate(1);
+                self.builded = false;
+            }
+            1 => {
+                let root = hash_single(&self.digest, self.mode, self.odd_node_policy, &self.layers[0][0]);
+                self.layers.truncate(2);
+                self.layers[1].clear();
+                self.layers[1].push(root);
+            }
+            _ => {
+                let mut start = leaf_index;
+                let mut layer_index = 0;
+                while self.layers[layer_index].len() > 1 {
+                    if self.layers.len() == layer_index + 1 {
+                        self.layers.push(Vec::with_capacity(1));
+                    }
+                    let layer_len = self.layers[layer_index].len();
+                    let next_len = layer_len.div_ceil(2);
+                    let first_parent = (start / 2).min(next_len);
+                    let new_nodes: Vec<D::Node> = {
+                        let layer = &self.layers[layer_index];
+                        let digest = &self.digest;
+                        let mode = self.mode;
+                        let policy = self.odd_node_policy;
+                        let hash_parent = |parent: usize| {
+                            let left_index = parent * 2;
+                            if left_index + 1 < layer_len {
+                                hash_node(digest, mode, &layer[left_index], &layer[left_index + 1])
+                            } else {
+                                hash_lonely(digest, mode, policy, &layer[left_index])
+                            }
+                        };
+                        if self.use_parallel(next_len - first_parent) {
+                            self.install(|| (first_parent..next_len).into_par_iter().map(&hash_parent).collect())
+                        } else {
+                            (first_parent..next_len).map(hash_parent).collect()
+                        }
+                    };
+                    let next_layer = &mut self.layers[layer_index + 1];
+                    next_layer.truncate(first_parent);
+                    next_layer.extend(new_nodes);
+                    start = first_parent;
+                    layer_index += 1;
+                }
+                self.layers.truncate(layer_index + 1);
+            }
         }
     }
 
@@ -202,32 +576,32 @@ impl MerkleTree {
                 self.layers.push(Vec::with_capacity(4));
                 let left = self.layers[layer_index][0];
                 let right = self.layers[layer_index][1];
-                let new_node = hash_node(&left, &right);
+                let new_node = hash_node(&self.digest, self.mode, &left, &right);
+                self.layers[layer_index + 1].push(new_node);
+            } else if self.layers[layer_index].len() % 2 == 1 {
+                let last_node_index = self.layers[layer_index].len() - 1;
+                let last_node = self.layers[layer_index][last_node_index];
+                let new_node = hash_lonely(&self.digest,
+                                           self.mode,
+                                           self.odd_node_policy,
+                                           &last_node);
                 self.layers[layer_index + 1].push(new_node);
+                self.recursive_repair_branch(layer_index + 1);
             } else {
-                if self.layers[layer_index].len() % 2 == 1 {
-                    let last_node_index = self.layers[layer_index].len() - 1;
-                    let last_node = self.layers[layer_index][last_node_index];
-                    let new_node = hash_leaf(&last_node);
-                    self.layers[layer_index + 1].push(new_node);
-                    self.recursive_repair_branch(layer_index + 1);
-                } else {
-                    for current_index in layer_index..self.layers.len() - 1 {
-                        let last_node_index = self.layers[current_index].len() - 1;
-                        let new_node;
-                        if self.layers[current_index].len() % 2 == 0 {
-                            let left = self.layers[current_index][last_node_index - 1];
-                            let right = self.layers[current_index][last_node_index];
-                            new_node = hash_node(&left, &right);
-                        } else {
-                            let right = self.layers[current_index][last_node_index];
-                            new_node = hash_leaf(&right);
-                        }
-                        debug!("Next layer len: {}", self.layers[current_index + 1].len());
-                        let next_layer_last_index = self.layers[current_index + 1].len() - 1;
-                        self.layers[current_index + 1].remove(next_layer_last_index);
-                        self.layers[current_index + 1].push(new_node);
-                    }
+                for current_index in layer_index..self.layers.len() - 1 {
+                    let last_node_index = self.layers[current_index].len() - 1;
+                    let new_node = if self.layers[current_index].len().is_multiple_of(2) {
+                        let left = self.layers[current_index][last_node_index - 1];
+                        let right = self.layers[current_index][last_node_index];
+                        hash_node(&self.digest, self.mode, &left, &right)
+                    } else {
+                        let right = self.layers[current_index][last_node_index];
+                        hash_lonely(&self.digest, self.mode, self.odd_node_policy, &right)
+                    };
+                    debug!("Next layer len: {}", self.layers[current_index + 1].len());
+                    let next_layer_last_index = self.layers[current_index + 1].len() - 1;
+                    self.layers[current_index + 1].remove(next_layer_last_index);
+                    self.layers[current_index + 1].push(new_node);
                 }
             }
         }
@@ -248,100 +622,442 @@ impl MerkleTree {
     }
 
     /// Производит поиск хэша транзакции в слое "листьев" дерева и поднимаясь до корня рекурсивно
-    /// проверяет корректность всех хэшей на пути для этого хэша транзакции. Возвращает так называемый
-    /// proof path - т.е список хэшей, который подтверждает, что хэш транзакции был использован при
-    /// построении дерева.
+    /// проверяет корректность всех хэшей на пути для этого хэша транзакции. Возвращает
+    /// доказательство включения - хэши соседних узлов на пути к корню с указанием их положения,
+    /// которого достаточно, чтобы подтвердить, что хэш транзакции был использован при
+    /// построении дерева. Если хэш встречается в слое "листьев" несколько раз, доказательство
+    /// строится для первого вхождения, остальные можно найти через `find_leaves`.
     ///
     /// # Failures
     ///
-    /// В случае, если дерево не было построено возвращается ошибка `Tree not builded`.
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
     /// В случае, если какой-либо из узлов на пути инвалидирован, т.е хэш актуальных в дереве значений
-    /// не совпадает с вычисленным либо наоборот - возвращается ошибка `Tree invalidate`.
+    /// не совпадает с вычисленным либо наоборот - возвращается ошибка `MerkleError::Corrupted`.
     ///
-    pub fn audit_proof(&mut self, hash: &[u8; 32]) -> Result<Vec<[u8; 32]>, &str> {
+    pub fn audit_proof(&mut self, hash: &D::Node) -> Result<Proof<D>, MerkleError> {
         if !self.builded {
-            return Err("Tree not builded");
+            return Err(MerkleError::NotBuilt);
         }
-        if self.layers[0].len() == 1 {
-            let root_hash = hash_leaf(hash);
-            if root_hash == self.layers[1][0] {
-                return Ok(vec![root_hash]);
-            } else {
-                return Err("Tree invalidate.");
+        match self.layers[0]
+            .iter()
+            .position(|element| element == hash) {
+            Some(leaf_index) => self.proof_by_index(leaf_index),
+            None => Err(MerkleError::LeafNotFound),
+        }
+    }
+
+    /// Сериализует значение в формате дерева, хэширует его так же, как при добавлении, и возвращает
+    /// доказательства включения для всех "листьев" с таким хэшем в порядке возрастания индексов.
+    ///
+    /// # Failures
+    ///
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// В случае, если значение не удалось сериализовать - `MerkleError::Serialization`.
+    /// В случае, если значение не найдено - `MerkleError::LeafNotFound`.
+    /// В случае, если какой-либо из узлов на пути инвалидирован - `MerkleError::Corrupted`.
+    ///
+    pub fn proof_for_value<Serializable>(&self, value: &Serializable) -> Result<Vec<Proof<D>>, MerkleError>
+        where Serializable: Serialize
+    {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        let serialized_value = self.format.serialize(value)?;
+        let hash = hash_leaf(&self.digest, self.mode, &serialized_value);
+        let leaf_indices = self.find_leaves(&hash);
+        if leaf_indices.is_empty() {
+            return Err(MerkleError::LeafNotFound);
+        }
+        leaf_indices.into_iter()
+            .map(|leaf_index| self.proof_by_index(leaf_index))
+            .collect()
+    }
+
+    /// Возвращает индексы всех "листьев" с переданным хэшем в порядке возрастания.
+    pub fn find_leaves(&self, hash: &D::Node) -> Vec<usize> {
+        self.layers[0]
+            .iter()
+            .enumerate()
+            .filter(|&(_, element)| element == hash)
+            .map(|(leaf_index, _)| leaf_index)
+            .collect()
+    }
+
+    /// Возвращает доказательство включения сразу для нескольких "листьев" с переданными индексами.
+    /// Индексы сортируются и повторы отбрасываются. Узлы, общие для путей нескольких "листьев",
+    /// попадают в доказательство один раз, а узлы, вычислимые из самих "листьев", не попадают вовсе.
+    ///
+    /// # Failures
+    ///
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// В случае, если список индексов пуст или какой-либо индекс выходит за пределы слоя "листьев" -
+    /// возвращаются ошибки `MerkleError::NoLeafIndices` и `MerkleError::IndexOutOfRange` соответственно.
+    ///
+    pub fn multi_proof(&self, leaf_indices: &[usize]) -> Result<MultiProof<D>, MerkleError> {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        if leaf_indices.is_empty() {
+            return Err(MerkleError::NoLeafIndices);
+        }
+        if let Some(&index) = leaf_indices.iter().find(|&&index| index >= self.layers[0].len()) {
+            return Err(MerkleError::IndexOutOfRange { index, len: self.layers[0].len() });
+        }
+        let mut indices = leaf_indices.to_vec();
+        indices.sort();
+        indices.dedup();
+        let mut nodes = Vec::new();
+        let mut known = indices.clone();
+        let mut layer_index = 0;
+        while self.layers[layer_index].len() > 1 {
+            let layer = &self.layers[layer_index];
+            let mut parents = Vec::with_capacity(known.len());
+            let mut position = 0;
+            while position < known.len() {
+                let index = known[position];
+                if !index.is_multiple_of(2) {
+                    nodes.push(layer[index - 1]);
+                } else if index + 1 < layer.len() {
+                    if position + 1 < known.len() && known[position + 1] == index + 1 {
+                        position += 1;
+                    } else {
+                        nodes.push(layer[index + 1]);
+                    }
+                }
+                parents.push(index / 2);
+                position += 1;
             }
+            known = parents;
+            layer_index += 1;
+        }
+        Ok(MultiProof {
+            leaf_indices: indices,
+            tree_size: self.layers[0].len(),
+            nodes,
+            digest: self.digest.clone(),
+            mode: self.mode,
+            odd_node_policy: self.odd_node_policy,
+        })
+    }
+
+    /// Возвращает доказательство согласованности по RFC 6962: дерево из первых `old_size` "листьев"
+    /// является префиксом дерева из первых `new_size` "листьев".
+    ///
+    /// # Failures
+    ///
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// Доказательства согласованности определены только для деревьев с правилом
+    /// `OddNodePolicy::Promote`, для остальных правил возвращается `MerkleError::UnsupportedPolicy`.
+    /// В случае, если `old_size` равен нулю, больше `new_size` или `new_size` больше количества
+    /// "листьев" - возвращается ошибка `MerkleError::InvalidTreeSizes`.
+    ///
+    pub fn consistency_proof(&self,
+                             old_size: usize,
+                             new_size: usize)
+                             -> Result<ConsistencyProof<D>, MerkleError> {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        if self.odd_node_policy != OddNodePolicy::Promote {
+            return Err(MerkleError::UnsupportedPolicy(self.odd_node_policy));
+        }
+        if old_size == 0 || old_size > new_size || new_size > self.layers[0].len() {
+            return Err(MerkleError::InvalidTreeSizes { old_size, new_size });
+        }
+        let mut path = Vec::new();
+        if old_size < new_size {
+            self.consistency_subproof(old_size, 0, new_size, true, &mut path);
+        }
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            path,
+            digest: self.digest.clone(),
+            mode: self.mode,
+        })
+    }
+
+    /// Алгоритм SUBPROOF из RFC 6962 для поддерева из "листьев" `start..end`, в котором первые
+    /// `old_size` "листьев" образуют старое дерево.
+    fn consistency_subproof(&self,
+                            old_size: usize,
+                            start: usize,
+                            end: usize,
+                            whole_subtree: bool,
+                            path: &mut Vec<D::Node>) {
+        let size = end - start;
+        if old_size == size {
+            if !whole_subtree {
+                path.push(self.subtree_hash(start, end));
+            }
+            return;
+        }
+        let split = split_point(size);
+        if old_size <= split {
+            self.consistency_subproof(old_size, start, start + split, whole_subtree, path);
+            path.push(self.subtree_hash(start + split, end));
         } else {
-            match self.layers[0]
-                .iter()
-                .enumerate()
-                .find(|&(_, element)| *element == *hash) {
-                Some(data) => {
-                    let proof_path = Vec::with_capacity(self.layers.len());
-                    self.recursive_audit_path(*data.1, data.0, 0, proof_path)
-                },
-                None => {
-                    Err("Transaction hash not found in leaves layer.")
-                }
+            self.consistency_subproof(old_size - split, start + split, end, false, path);
+            path.push(self.subtree_hash(start, start + split));
+        }
+    }
+
+    /// Возвращает корень поддерева из "листьев" `start..end`. Полные выровненные поддеревья берутся
+    /// из слоёв дерева, остальные достраиваются по правилу RFC 6962.
+    fn subtree_hash(&self, start: usize, end: usize) -> D::Node {
+        let size = end - start;
+        if size.is_power_of_two() && start.is_multiple_of(size) {
+            let layer_index = size.trailing_zeros() as usize;
+            return self.layers[layer_index][start >> layer_index];
+        }
+        let split = split_point(size);
+        let left = self.subtree_hash(start, start + split);
+        let right = self.subtree_hash(start + split, end);
+        hash_node(&self.digest, self.mode, &left, &right)
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+    where D: Hasher,
+          S: NodeStore<D::Node>
+{
+    /// Создаёт дерево, узлы которого хранятся в переданном хранилище.
+    /// Если хранилище уже содержит построенное дерево (например, открытый `FileNodeStore`), после
+    /// создания следует установить флаг `builded` и правила, с которыми дерево было построено.
+    pub fn with_store(digest: D, store: S) -> MerkleTree<D, S> {
+        MerkleTree {
+            layers: store,
+            parallel: true,
+            min_parallel_layer_size: DEFAULT_MIN_PARALLEL_LAYER_SIZE,
+            store_batch_size: DEFAULT_STORE_BATCH_SIZE,
+            thread_pool: None,
+            builded: false,
+            format: SerializationFormat::MsgPack,
+            digest,
+            mode: TreeMode::Classic,
+            odd_node_policy: OddNodePolicy::Rehash,
+            removal_policy: RemovalPolicy::Tombstone,
+        }
+    }
+
+    /// Добавляет уже вычисленные хэши "листьев" в конец слоя "листьев" хранилища.
+    /// Узлы дерева не пересчитываются до вызова `build_store`.
+    ///
+    /// # Failures
+    /// В случае ошибки хранилища возвращает её, состояние построения дерева при этом не меняется.
+    pub fn append_leaves(&mut self, hashes: &[D::Node]) -> Result<(), MerkleError> {
+        let leaves_len = self.layers.layer_len(0);
+        self.layers.put_batch(0, leaves_len, hashes)?;
+        self.builded = false;
+        Ok(())
+    }
+
+    /// Строит дерево в хранилище из слоя "листьев", читая и записывая узлы проходами по
+    /// `store_batch_size` родительских узлов, так что объём занятой памяти не зависит от
+    /// размера дерева. Если дерево уже было построено, пересчитываются только узлы, зависящие
+    /// от добавленных после этого "листьев". Возвращает Merkle Root Hash.
+    ///
+    /// # Failures
+    /// В случае, если слой "листьев" пуст возвращает `MerkleError::EmptyTree`, в случае ошибки
+    /// хранилища - её.
+    pub fn build_store(&mut self) -> Result<D::Node, MerkleError> {
+        let leaves_len = self.layers.layer_len(0);
+        if leaves_len == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if leaves_len == 1 {
+            let leaf = self.layers.get(0, 0)?;
+            let root = hash_single(&self.digest, self.mode, self.odd_node_policy, &leaf);
+            self.layers.put(1, 0, root)?;
+        }
+        let batch_size = self.store_batch_size.max(1);
+        let mut layer_index = 0;
+        let mut layer_len = leaves_len;
+        while layer_len > 1 {
+            let parents_len = layer_len.div_ceil(2);
+            let mut parent_index = self.layers.layer_len(layer_index + 1).saturating_sub(1);
+            while parent_index < parents_len {
+                let batch_len = batch_size.min(parents_len - parent_index);
+                let first_child = parent_index * 2;
+                let children_len = (batch_len * 2).min(layer_len - first_child);
+                let children = self.layers.get_batch(layer_index, first_child, children_len)?;
+                let parents = self.hash_pairs(&children);
+                self.layers.put_batch(layer_index + 1, parent_index, &parents)?;
+                parent_index += batch_len;
             }
+            layer_index += 1;
+            layer_len = parents_len;
+        }
+        self.builded = true;
+        self.get_merkle_root()
+    }
+
+    /// Вычисляет родительские узлы для узлов слоя, начинающихся с чётного индекса.
+    fn hash_pairs(&self, children: &[D::Node]) -> Vec<D::Node> {
+        let digest = &self.digest;
+        let mode = self.mode;
+        let policy = self.odd_node_policy;
+        let hash_pair = |pair: &[D::Node]| if pair.len() == 2 {
+            hash_node(digest, mode, &pair[0], &pair[1])
+        } else {
+            hash_lonely(digest, mode, policy, &pair[0])
+        };
+        if self.use_parallel(children.len()) {
+            self.install(|| children.par_chunks(2).map(hash_pair).collect())
+        } else {
+            children.chunks(2).map(hash_pair).collect()
+        }
+    }
 
+    /// Возвращает merkle root hash в качестве массива байт размером с выход хэш-функции.
+    ///
+    /// # Failures
+    /// В случае, если в дереве нет "листьев" возвращает `MerkleError::EmptyTree`, если дерево не было
+    /// построено - `MerkleError::NotBuilt`.
+    pub fn get_merkle_root(&self) -> Result<D::Node, MerkleError> {
+        if self.layers.layer_len(0) == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        let root_layer = self.layers.layers_count() - 1;
+        match self.layers.layer_len(root_layer) {
+            0 => Err(MerkleError::EmptyTree),
+            root_layer_len => self.layers.get(root_layer, root_layer_len - 1),
         }
+    }
 
+    /// Возвращает доказательство включения для "листа" с переданным индексом, проверяя
+    /// корректность всех хэшей на пути к корню.
+    ///
+    /// # Failures
+    ///
+    /// В случае, если дерево не было построено возвращается ошибка `MerkleError::NotBuilt`.
+    /// В случае, если индекс выходит за пределы слоя "листьев" - `MerkleError::IndexOutOfRange`.
+    /// В случае, если какой-либо из узлов на пути инвалидирован - `MerkleError::Corrupted`.
+    ///
+    pub fn proof_by_index(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        if !self.builded {
+            return Err(MerkleError::NotBuilt);
+        }
+        let leaves_len = self.layers.layer_len(0);
+        if leaf_index >= leaves_len {
+            return Err(MerkleError::IndexOutOfRange { index: leaf_index, len: leaves_len });
+        }
+        let hash = self.layers.get(0, leaf_index)?;
+        if leaves_len == 1 {
+            let root_hash = hash_single(&self.digest, self.mode, self.odd_node_policy, &hash);
+            if root_hash != self.layers.get(1, 0)? {
+                return Err(MerkleError::Corrupted { layer: 1, index: 0 });
+            }
+        }
+        let proof_path = Vec::with_capacity(self.layers.layers_count());
+        let path = self.recursive_audit_path(hash, leaf_index, 0, proof_path)?;
+        Ok(Proof {
+            leaf_index,
+            tree_size: leaves_len,
+            path,
+            digest: self.digest.clone(),
+            mode: self.mode,
+            odd_node_policy: self.odd_node_policy,
+        })
     }
 
     /// Поднимается до корня рекурсивно, проверяет корректность всех хэшей на пути для этого хэша транзакции.
-    /// Возвращает так называемый proof path - т.е список хэшей, который подтверждает, что хэш транзакции был использован при
-    /// построении дерева.
+    /// Возвращает хэши соседних узлов на пути с указанием их положения. Узлы, которым не нашлось
+    /// пары в конце слоя, соседей не имеют и в путь не попадают.
     ///
     /// # Failures
     /// В случае, если какой-либо из узлов на пути инвалидирован, т.е хэш актуальных в дереве значений
-    /// не совпадает с вычисленным либо наоборот - возвращается ошибка `Tree invalidate`.
+    /// не совпадает с вычисленным либо наоборот - возвращается ошибка `MerkleError::Corrupted`.
     ///
     pub fn recursive_audit_path(&self,
-                                hash: [u8; 32],
+                                hash: D::Node,
                                 hash_index: usize,
                                 layer_index: usize,
-                                mut path: Vec<[u8; 32]>)
-                                -> Result<Vec<[u8; 32]>, &str> {
-        if self.layers[layer_index].len() > 1 {
-            let pair;
-            let expected_node;
-            if hash_index % 2 != 0 {
-                pair = self.layers[layer_index][hash_index - 1];
-                expected_node = hash_node(&pair, &hash);
+                                mut path: Vec<ProofNode<D::Node>>)
+                                -> Result<Vec<ProofNode<D::Node>>, MerkleError> {
+        let layer_len = self.layers.layer_len(layer_index);
+        if layer_len > 1 {
+            let expected_node = if !hash_index.is_multiple_of(2) {
+                let pair = self.layers.get(layer_index, hash_index - 1)?;
+                path.push(ProofNode { hash: pair, position: Position::Left });
+                hash_node(&self.digest, self.mode, &pair, &hash)
+            } else if hash_index + 1 < layer_len {
+                let pair = self.layers.get(layer_index, hash_index + 1)?;
+                path.push(ProofNode { hash: pair, position: Position::Right });
+                hash_node(&self.digest, self.mode, &hash, &pair)
             } else {
-                pair = self.layers[layer_index][hash_index + 1];
-                expected_node = hash_node(&hash, &pair);
-            }
+                hash_lonely(&self.digest, self.mode, self.odd_node_policy, &hash)
+            };
             let parent_index = hash_index / 2;
-            let actual_node = self.layers[layer_index + 1][parent_index];
+            let actual_node = self.layers.get(layer_index + 1, parent_index)?;
             if expected_node != actual_node {
-                return Err("Tree invalidate.");
+                Err(MerkleError::Corrupted { layer: layer_index + 1, index: parent_index })
             } else {
-                path.push(expected_node.clone());
                 self.recursive_audit_path(expected_node, parent_index, layer_index + 1, path)
             }
         } else {
             Ok(path)
         }
     }
+
+    /// Определяет, обрабатывать ли `len` элементов слоя многопоточно.
+    fn use_parallel(&self, len: usize) -> bool {
+        self.parallel && len >= self.min_parallel_layer_size
+    }
+
+    /// Выполняет многопоточную операцию в пуле `thread_pool`, либо в глобальном пуле rayon.
+    fn install<Operation, Output>(&self, operation: Operation) -> Output
+        where Operation: FnOnce() -> Output + Send,
+              Output: Send
+    {
+        match self.thread_pool {
+            Some(ref thread_pool) => thread_pool.install(operation),
+            None => operation(),
+        }
+    }
+}
+
+/// Наибольшая степень двойки, строго меньшая `size` (`size` > 1).
+fn split_point(size: usize) -> usize {
+    1 << (usize::BITS - 1 - (size - 1).leading_zeros())
 }
 
 #[cfg(test)]
 mod tests {
 
     extern crate env_logger;
-    use super::MerkleTree;
+    use super::{MerkleTree, DEFAULT_MIN_PARALLEL_LAYER_SIZE};
+    use std::sync::Arc;
+    use rayon::ThreadPool;
     use super::SerializationFormat;
     use super::{hash_leaf, hash_node};
+    use tree_mode::TreeMode;
+    use odd_node_policy::OddNodePolicy;
+    use removal_policy::RemovalPolicy;
+    use proof::{Position, ProofNode};
+    use merkle_error::MerkleError;
+    use std::collections::BTreeMap;
+    use crypto::digest::Digest;
+    use crypto::sha1::Sha1;
+    use crypto::sha2::{Sha256, Sha512};
+    use crypto::sha3::Sha3;
+    use crypto::ripemd160::Ripemd160;
+    use hasher::{Blake2b256, Sha3_256};
+    use test_helpers::{RFC6962_LEAVES, ODD_NODE_POLICIES, bad_record};
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn build_tree() {
         let _ = env_logger::init();
         let mut merkle_tree_sequence: MerkleTree = MerkleTree::default();
         merkle_tree_sequence.parallel = false;
-        merkle_tree_sequence.push(&["a"]);
+        merkle_tree_sequence.push(&["a"]).unwrap();
         merkle_tree_sequence.build().unwrap();
         let mut merkle_tree_parallel: MerkleTree = MerkleTree::default();
-        merkle_tree_parallel.push(&["a"]);
+        merkle_tree_parallel.push(&["a"]).unwrap();
         merkle_tree_parallel.build().unwrap();
         assert_eq!(merkle_tree_sequence.layers, merkle_tree_parallel.layers);
     }
@@ -350,14 +1066,14 @@ mod tests {
     fn build_empty_tree() {
         let _ = env_logger::init();
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        assert_eq!(merkle_tree.build(), Err("No leaves in tree"));
+        assert_eq!(merkle_tree.build(), Err(MerkleError::EmptyTree));
     }
 
     #[test]
     fn build_tree_one_leaf() {
         let _ = env_logger::init();
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        merkle_tree.push(&["a"]);
+        merkle_tree.push(&["a"]).unwrap();
         merkle_tree.build().unwrap();
         merkle_tree.print();
     }
@@ -366,8 +1082,8 @@ mod tests {
     fn build_tree_two_leaf() {
         let _ = env_logger::init();
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
         merkle_tree.build().unwrap();
     }
 
@@ -375,9 +1091,9 @@ mod tests {
     fn build_tree_three_leaf() {
         let _ = env_logger::init();
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
         merkle_tree.build().unwrap();
     }
 
@@ -385,10 +1101,10 @@ mod tests {
     fn build_tree_four_leaf() {
         let _ = env_logger::init();
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
-        merkle_tree.push(&["a"]);
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
+        merkle_tree.push(&["a"]).unwrap();
         merkle_tree.build().unwrap();
         merkle_tree.print();
     }
@@ -396,8 +1112,8 @@ mod tests {
     #[test]
     fn build_tree_from_leaves() {
         let _ = env_logger::init();
-        let mut merkle_tree: MerkleTree = MerkleTree::from(&mut ["a", "b", "c", "d"],
-                                                           SerializationFormat::Json);
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d"],
+                                                           SerializationFormat::Json).unwrap();
         merkle_tree.print();
         merkle_tree.build().unwrap();
         merkle_tree.print();
@@ -406,36 +1122,273 @@ mod tests {
     #[test]
     fn repair_branch() {
         let _ = env_logger::init();
-        let mut merkle_tree: MerkleTree = MerkleTree::from(&mut ["a", "b"],
-                                                           SerializationFormat::Json);
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b"],
+                                                           SerializationFormat::Json).unwrap();
         merkle_tree.print();
         merkle_tree.build().unwrap();
-        let serialized_hashed_a =
-            hash_leaf(&SerializationFormat::Json.serialize(&String::from("a")));
+        let serialized_hashed_a = hash_leaf(&Sha256::new(),
+                                            TreeMode::Classic,
+                                            &SerializationFormat::Json.serialize(&String::from("a")).unwrap());
         debug!("serialized hashed a: {:?}", serialized_hashed_a);
-        let serialized_hashed_b =
-            hash_leaf(&SerializationFormat::Json.serialize(&String::from("b")));
+        let serialized_hashed_b = hash_leaf(&Sha256::new(),
+                                            TreeMode::Classic,
+                                            &SerializationFormat::Json.serialize(&String::from("b")).unwrap());
         debug!("serialized hashed a: {:?}", serialized_hashed_a);
-        let merkle_root_hash_of_a_and_b = hash_node(&serialized_hashed_a, &serialized_hashed_b);
+        let merkle_root_hash_of_a_and_b = hash_node(&Sha256::new(),
+                                                    TreeMode::Classic,
+                                                    &serialized_hashed_a,
+                                                    &serialized_hashed_b);
         debug!("merkle root hash of a and b transactions: {:?}",
                merkle_root_hash_of_a_and_b);
         assert_eq!(merkle_root_hash_of_a_and_b, merkle_tree.get_merkle_root().unwrap());
         merkle_tree.print();
-        let mut merkle_tree_two: MerkleTree = MerkleTree::from(&mut ["a"],
-                                                               SerializationFormat::Json);
+        let mut merkle_tree_two: MerkleTree = MerkleTree::from(&["a"],
+                                                               SerializationFormat::Json).unwrap();
         merkle_tree_two.build().unwrap();
-        merkle_tree_two.push(&String::from("b"));
+        merkle_tree_two.push(&String::from("b")).unwrap();
         merkle_tree_two.print();
         assert_eq!(merkle_tree_two.get_merkle_root().unwrap(),
                    merkle_root_hash_of_a_and_b);
         assert_eq!(merkle_tree_two, merkle_tree);
     }
 
+    #[test]
+    fn build_tree_with_sha3() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from_with_digest(&["a", "b"],
+                                                           SerializationFormat::Json,
+                                                           Sha3_256::new()).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let digest = Sha3_256::new();
+        let json_a = SerializationFormat::Json.serialize(&"a").unwrap();
+        let json_b = SerializationFormat::Json.serialize(&"b").unwrap();
+        let hashed_a = hash_leaf(&digest, TreeMode::Classic, &json_a);
+        let hashed_b = hash_leaf(&digest, TreeMode::Classic, &json_b);
+        assert_eq!(hash_node(&digest, TreeMode::Classic, &hashed_a, &hashed_b), root);
+        let mut sha3_hex = Sha3::sha3_256();
+        sha3_hex.input(&SerializationFormat::Json.serialize(&"a").unwrap());
+        let mut expected_leaf = [0; 32];
+        sha3_hex.result(&mut expected_leaf);
+        assert_eq!(expected_leaf, merkle_tree.layers[0][0]);
+    }
+
+    #[test]
+    fn build_tree_with_different_digests() {
+        let _ = env_logger::init();
+        let leaves = ["a", "b", "c"];
+        let sha256_root = MerkleTree::from(&leaves, SerializationFormat::Json).unwrap().build().unwrap();
+        let sha3_root = MerkleTree::from_with_digest(&leaves,
+                                                     SerializationFormat::Json,
+                                                     Sha3_256::new()).unwrap()
+            .build()
+            .unwrap();
+        let blake2b_root = MerkleTree::from_with_digest(&leaves,
+                                                        SerializationFormat::Json,
+                                                        Blake2b256::new()).unwrap()
+            .build()
+            .unwrap();
+        assert!(sha3_root != sha256_root);
+        assert!(blake2b_root != sha256_root);
+        assert!(blake2b_root != sha3_root);
+    }
+
+    #[test]
+    fn node_size_follows_digest() {
+        let _ = env_logger::init();
+        let leaves = ["a", "b", "c"];
+        let mut sha1_tree = MerkleTree::from_with_digest(&leaves,
+                                                         SerializationFormat::Json,
+                                                         Sha1::new()).unwrap();
+        let sha1_root: [u8; 20] = sha1_tree.build().unwrap();
+        let mut sha1 = Sha1::new();
+        sha1.input(&SerializationFormat::Json.serialize(&"a").unwrap());
+        let mut expected_leaf = [0; 20];
+        sha1.result(&mut expected_leaf);
+        assert_eq!(expected_leaf, sha1_tree.layers[0][0]);
+        assert_eq!(sha1_root, sha1_tree.get_merkle_root().unwrap());
+        let mut sha512_tree = MerkleTree::from_with_digest(&leaves,
+                                                           SerializationFormat::Json,
+                                                           Sha512::new()).unwrap();
+        let sha512_root: [u8; 64] = sha512_tree.build().unwrap();
+        let sha512_leaf = sha512_tree.layers[0][0];
+        let sha512_proof = sha512_tree.audit_proof(&sha512_leaf).unwrap();
+        assert_eq!(sha512_tree.layers[0][1], sha512_proof.path[0].hash);
+        assert_eq!(sha512_root, sha512_tree.layers[2][0]);
+        let mut ripemd160_tree = MerkleTree::from_with_digest(&leaves,
+                                                              SerializationFormat::Json,
+                                                              Ripemd160::new()).unwrap();
+        let ripemd160_root: [u8; 20] = ripemd160_tree.build().unwrap();
+        assert!(ripemd160_root != sha1_root);
+    }
+
+    #[test]
+    fn push_with_digest() {
+        let _ = env_logger::init();
+        let mut merkle_tree_from = MerkleTree::from_with_digest(&["a", "b", "c"],
+                                                                SerializationFormat::Json,
+                                                                Sha3_256::new()).unwrap();
+        merkle_tree_from.build().unwrap();
+        let mut merkle_tree_push = MerkleTree::with_digest(Sha3_256::new());
+        merkle_tree_push.format = SerializationFormat::Json;
+        merkle_tree_push.push(&"a").unwrap();
+        merkle_tree_push.build().unwrap();
+        merkle_tree_push.push(&"b").unwrap();
+        merkle_tree_push.push(&"c").unwrap();
+        assert_eq!(merkle_tree_from.get_merkle_root(), merkle_tree_push.get_merkle_root());
+    }
+
+    /// Корни деревьев из первых 1..8 "листьев" `RFC6962_LEAVES`.
+    const RFC6962_ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn rfc6962_test_vectors() {
+        let _ = env_logger::init();
+        for size in 1..RFC6962_LEAVES.len() + 1 {
+            let mut merkle_tree = MerkleTree::rfc6962();
+            for leaf in &RFC6962_LEAVES[..size] {
+                merkle_tree.push_raw(leaf);
+            }
+            let root = merkle_tree.build().unwrap();
+            assert_eq!(RFC6962_ROOTS[size - 1], to_hex(&root));
+        }
+    }
+
+    #[test]
+    fn rfc6962_push_after_build() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::rfc6962();
+        merkle_tree.parallel = false;
+        merkle_tree.push_raw(RFC6962_LEAVES[0]);
+        merkle_tree.build().unwrap();
+        for (size, leaf) in RFC6962_LEAVES.iter().enumerate().skip(1) {
+            merkle_tree.push_raw(leaf);
+            assert_eq!(RFC6962_ROOTS[size], to_hex(&merkle_tree.get_merkle_root().unwrap()));
+        }
+    }
+
+    #[test]
+    fn rfc6962_domain_separation() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::rfc6962();
+        merkle_tree.push_raw(b"a");
+        merkle_tree.push_raw(b"b");
+        let root = merkle_tree.build().unwrap();
+        let mut forged_tree = MerkleTree::rfc6962();
+        let mut interior = Vec::new();
+        interior.extend_from_slice(&merkle_tree.layers[0][0]);
+        interior.extend_from_slice(&merkle_tree.layers[0][1]);
+        forged_tree.push_raw(&interior);
+        assert!(forged_tree.build().unwrap() != root);
+        let mut classic_tree = MerkleTree::default();
+        classic_tree.push_raw(b"a");
+        classic_tree.push_raw(b"b");
+        assert!(classic_tree.build().unwrap() != root);
+    }
+
+    #[test]
+    fn rfc6962_audit_proof_lonely_leaf() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::rfc6962();
+        for leaf in &RFC6962_LEAVES[..5] {
+            merkle_tree.push_raw(leaf);
+        }
+        merkle_tree.build().unwrap();
+        let lonely_leaf = merkle_tree.layers[0][4];
+        let proof = merkle_tree.audit_proof(&lonely_leaf).unwrap();
+        assert_eq!(vec![ProofNode { hash: merkle_tree.layers[2][0], position: Position::Left }],
+                   proof.path);
+        assert_eq!(RFC6962_ROOTS[4], to_hex(&proof.root(&lonely_leaf).unwrap()));
+    }
+
+    #[test]
+    fn odd_node_policy_three_leaves() {
+        let _ = env_logger::init();
+        let digest = Sha256::new();
+        let mode = TreeMode::Classic;
+        let leaves: Vec<[u8; 32]> = ["a", "b", "c"]
+            .iter()
+            .map(|leaf| hash_leaf(&digest, mode, &SerializationFormat::Json.serialize(leaf).unwrap()))
+            .collect();
+        let left = hash_node(&digest, mode, &leaves[0], &leaves[1]);
+        let expected_roots = [
+            hash_node(&digest, mode, &left, &hash_leaf(&digest, mode, &leaves[2])),
+            hash_node(&digest, mode, &left, &hash_node(&digest, mode, &leaves[2], &leaves[2])),
+            hash_node(&digest, mode, &left, &leaves[2]),
+            hash_node(&digest, mode, &left, &hash_node(&digest, mode, &leaves[2], &[0; 32])),
+        ];
+        for (policy, expected_root) in ODD_NODE_POLICIES.iter().zip(expected_roots.iter()) {
+            let mut merkle_tree = MerkleTree::from(&["a", "b", "c"], SerializationFormat::Json).unwrap();
+            merkle_tree.odd_node_policy = *policy;
+            assert_eq!(*expected_root, merkle_tree.build().unwrap());
+        }
+    }
+
+    #[test]
+    fn odd_node_policy_single_leaf() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            let mut merkle_tree = MerkleTree::from(&["a"], SerializationFormat::Json).unwrap();
+            merkle_tree.odd_node_policy = *policy;
+            let leaf = merkle_tree.layers[0][0];
+            let root = merkle_tree.build().unwrap();
+            assert_eq!(*policy != OddNodePolicy::Rehash, root == leaf);
+            let proof = merkle_tree.audit_proof(&leaf).unwrap();
+            assert_eq!((0, 1), (proof.leaf_index, proof.tree_size));
+            assert!(proof.path.is_empty());
+        }
+    }
+
+    #[test]
+    fn odd_node_policy_build_and_push_agree() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..12 {
+                let leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree_parallel = MerkleTree::from(&leaves, SerializationFormat::Json).unwrap();
+                merkle_tree_parallel.odd_node_policy = *policy;
+                let root = merkle_tree_parallel.build().unwrap();
+                let mut merkle_tree_sequence = MerkleTree::from(&leaves, SerializationFormat::Json).unwrap();
+                merkle_tree_sequence.odd_node_policy = *policy;
+                merkle_tree_sequence.parallel = false;
+                assert_eq!(root, merkle_tree_sequence.build().unwrap());
+                let mut merkle_tree_push: MerkleTree = MerkleTree {
+                    format: SerializationFormat::Json,
+                    odd_node_policy: *policy,
+                    ..MerkleTree::default()
+                };
+                merkle_tree_push.push(&leaves[0]).unwrap();
+                merkle_tree_push.build().unwrap();
+                for leaf in &leaves[1..] {
+                    merkle_tree_push.push(leaf).unwrap();
+                }
+                assert_eq!(root, merkle_tree_push.get_merkle_root().unwrap());
+                for (leaf_index, leaf) in merkle_tree_parallel.layers[0].clone().iter().enumerate() {
+                    let proof = merkle_tree_parallel.audit_proof(leaf).unwrap();
+                    assert_eq!((leaf_index, size), (proof.leaf_index, proof.tree_size));
+                    assert!(proof.verify(&root, leaf));
+                }
+            }
+        }
+    }
+
     #[test]
     fn audit_proof() {
         let _ = env_logger::init();
-        let mut merkle_tree: MerkleTree = MerkleTree::from(&mut ["a", "b", "c", "d", "e"],
-                                                           SerializationFormat::Json);
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                                           SerializationFormat::Json).unwrap();
         merkle_tree.print();
         merkle_tree.build().unwrap();
         merkle_tree.print();
@@ -449,29 +1402,402 @@ mod tests {
                          5, 32],
                         [50, 111, 53, 115, 90, 175, 184, 3, 43, 167, 87, 45, 15, 87, 106, 177,
                          119, 3, 240, 177, 194, 92, 104, 105, 85, 17, 37, 18, 59, 224, 113, 39]],
-                   proof_path);
+                   vec![merkle_tree.layers[1][0], merkle_tree.layers[2][0], merkle_tree.layers[3][0]]);
+        assert_eq!(0, proof_path.leaf_index);
+        assert_eq!(5, proof_path.tree_size);
+        assert_eq!(vec![ProofNode { hash: merkle_tree.layers[0][1], position: Position::Right },
+                        ProofNode { hash: merkle_tree.layers[1][1], position: Position::Right },
+                        ProofNode { hash: merkle_tree.layers[2][1], position: Position::Right }],
+                   proof_path.path);
+    }
+
+    #[test]
+    fn proof_by_index() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                                           SerializationFormat::Json).unwrap();
+        assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.proof_by_index(0));
+        let root = merkle_tree.build().unwrap();
+        for leaf_index in 0..5 {
+            let leaf_hash = merkle_tree.layers[0][leaf_index];
+            let proof = merkle_tree.proof_by_index(leaf_index).unwrap();
+            assert_eq!(leaf_index, proof.leaf_index);
+            assert!(proof.verify(&root, &leaf_hash));
+            assert_eq!(Ok(proof), merkle_tree.audit_proof(&leaf_hash));
+        }
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 5, len: 5 }),
+                   merkle_tree.proof_by_index(5));
+    }
+
+    #[test]
+    fn proof_for_duplicate_values() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "a", "c", "a"],
+                                                           SerializationFormat::Json).unwrap();
+        assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.proof_for_value(&"a").map(|_| ()));
+        let root = merkle_tree.build().unwrap();
+        let leaf_hash = merkle_tree.layers[0][0];
+        assert_eq!(vec![0, 2, 4], merkle_tree.find_leaves(&leaf_hash));
+        let proofs = merkle_tree.proof_for_value(&"a").unwrap();
+        assert_eq!(vec![0, 2, 4], proofs.iter().map(|proof| proof.leaf_index).collect::<Vec<_>>());
+        for proof in &proofs {
+            assert!(proof.verify_value(&root, &"a", &SerializationFormat::Json));
+        }
+        assert_eq!(1, merkle_tree.proof_for_value(&"c").unwrap().len());
+        assert_eq!(Err(MerkleError::LeafNotFound),
+                   merkle_tree.proof_for_value(&"d").map(|_| ()));
+    }
+
+    #[test]
+    fn proof_for_value_single_leaf() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::default();
+        merkle_tree.push(&42u64).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let proofs = merkle_tree.proof_for_value(&42u64).unwrap();
+        assert_eq!(1, proofs.len());
+        assert!(proofs[0].verify_value(&root, &42u64, &SerializationFormat::MsgPack));
+    }
+
+    #[test]
+    fn proof_reports_corrupted_node() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                                           SerializationFormat::Json).unwrap();
+        merkle_tree.build().unwrap();
+        merkle_tree.layers[1][1][0] ^= 1;
+        assert_eq!(Err(MerkleError::Corrupted { layer: 1, index: 1 }),
+                   merkle_tree.proof_by_index(2));
+        assert!(merkle_tree.proof_by_index(4).is_ok());
+    }
+
+    #[test]
+    fn reject_unserializable_leaves() {
+        let _ = env_logger::init();
+        let bad_record = bad_record();
+        match MerkleTree::from(::std::slice::from_ref(&bad_record), SerializationFormat::Json) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b"], SerializationFormat::Json).unwrap();
+        let root = merkle_tree.build().unwrap();
+        match merkle_tree.push(&bad_record) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(2, merkle_tree.layers[0].len());
+        assert_eq!(Ok(root), merkle_tree.get_merkle_root());
+        match merkle_tree.proof_for_value(&bad_record) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let proof = merkle_tree.proof_by_index(0).unwrap();
+        assert!(!proof.verify_value(&root, &bad_record, &SerializationFormat::Json));
+    }
+
+    #[test]
+    fn update_leaf() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..12usize {
+                let mut leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                merkle_tree.odd_node_policy = *policy;
+                merkle_tree.build().unwrap();
+                for index in 0..size {
+                    leaves[index] += 100;
+                    let root = merkle_tree.update(index, &leaves[index]).unwrap();
+                    let mut expected_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                    expected_tree.odd_node_policy = *policy;
+                    assert_eq!(expected_tree.build().unwrap(), root);
+                    assert_eq!(expected_tree.layers, merkle_tree.layers);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_failures() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c"], SerializationFormat::Json).unwrap();
+        assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.update(0, &"d"));
+        let root = merkle_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+                   merkle_tree.update(3, &"d"));
+        let bad_record = bad_record();
+        match merkle_tree.update(1, &bad_record) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(Ok(root), merkle_tree.get_merkle_root());
+    }
+
+    fn build_with_policy(leaves: &[usize], policy: OddNodePolicy) -> MerkleTree {
+        let mut merkle_tree = MerkleTree::from(leaves, SerializationFormat::Bincode).unwrap();
+        merkle_tree.odd_node_policy = policy;
+        merkle_tree.build().unwrap();
+        merkle_tree
+    }
+
+    #[test]
+    fn truncate_tree() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..12usize {
+                let leaves: Vec<usize> = (0..size).collect();
+                for new_len in 1..size + 1 {
+                    let mut merkle_tree = build_with_policy(&leaves, *policy);
+                    merkle_tree.truncate(new_len).unwrap();
+                    let expected_tree = build_with_policy(&leaves[..new_len], *policy);
+                    assert_eq!(expected_tree.layers, merkle_tree.layers);
+                    merkle_tree.push(&size).unwrap();
+                    let mut expected_leaves = leaves[..new_len].to_vec();
+                    expected_leaves.push(size);
+                    let expected_tree = build_with_policy(&expected_leaves, *policy);
+                    assert_eq!(expected_tree.layers, merkle_tree.layers);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncate_to_empty() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c"], SerializationFormat::Json).unwrap();
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 4, len: 3 }), merkle_tree.truncate(4));
+        merkle_tree.build().unwrap();
+        merkle_tree.truncate(0).unwrap();
+        assert!(!merkle_tree.builded);
+        assert_eq!(Err(MerkleError::EmptyTree), merkle_tree.get_merkle_root());
+        merkle_tree.push(&"a").unwrap();
+        merkle_tree.push(&"b").unwrap();
+        let mut expected_tree: MerkleTree = MerkleTree::from(&["a", "b"], SerializationFormat::Json).unwrap();
+        assert_eq!(expected_tree.build(), merkle_tree.build());
+    }
+
+    #[test]
+    fn remove_with_shift() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..12usize {
+                let leaves: Vec<usize> = (0..size).collect();
+                for index in 0..size {
+                    let mut merkle_tree = build_with_policy(&leaves, *policy);
+                    merkle_tree.removal_policy = RemovalPolicy::Shift;
+                    merkle_tree.remove(index).unwrap();
+                    let mut expected_leaves = leaves.clone();
+                    expected_leaves.remove(index);
+                    if expected_leaves.is_empty() {
+                        assert_eq!(Err(MerkleError::EmptyTree), merkle_tree.get_merkle_root());
+                    } else {
+                        let expected_tree = build_with_policy(&expected_leaves, *policy);
+                        assert_eq!(expected_tree.layers, merkle_tree.layers);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn insert_hash_shifts_leaves() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 2..12usize {
+                let leaves: Vec<usize> = (0..size).collect();
+                let expected_tree = build_with_policy(&leaves, *policy);
+                for index in 0..size {
+                    let mut partial_leaves = leaves.clone();
+                    partial_leaves.remove(index);
+                    let mut merkle_tree = build_with_policy(&partial_leaves, *policy);
+                    merkle_tree.insert_hash(index, expected_tree.layers[0][index]).unwrap();
+                    assert_eq!(expected_tree.layers, merkle_tree.layers);
+                }
+                let mut merkle_tree = build_with_policy(&leaves, *policy);
+                assert_eq!(Err(MerkleError::IndexOutOfRange { index: size + 1, len: size }),
+                           merkle_tree.insert_hash(size + 1, [0; 32]));
+            }
+        }
+    }
+
+    #[test]
+    fn remove_with_tombstone() {
+        let _ = env_logger::init();
+        let leaves: Vec<usize> = (0..7).collect();
+        let mut merkle_tree = build_with_policy(&leaves, OddNodePolicy::Rehash);
+        let leaf_hashes = merkle_tree.layers[0].clone();
+        merkle_tree.remove(3).unwrap();
+        let root = merkle_tree.get_merkle_root().unwrap();
+        let mut expected_tree: MerkleTree = MerkleTree::default();
+        for (index, leaf_hash) in leaf_hashes.iter().enumerate() {
+            expected_tree.push_hash(if index == 3 { [0; 32] } else { *leaf_hash });
+        }
+        assert_eq!(expected_tree.build(), Ok(root));
+        assert_eq!(7, merkle_tree.layers[0].len());
+        for index in (0..7).filter(|&index| index != 3) {
+            let proof = merkle_tree.proof_by_index(index).unwrap();
+            assert_eq!(index, proof.leaf_index);
+            assert!(proof.verify_value(&root, &leaves[index], &SerializationFormat::Bincode));
+        }
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 7, len: 7 }), merkle_tree.remove(7));
+    }
+
+    #[test]
+    fn extend_after_build() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for parallel in [false, true].iter() {
+                for size in 1..10usize {
+                    for added in 0..10usize {
+                        let leaves: Vec<usize> = (0..size + added).collect();
+                        let mut merkle_tree = build_with_policy(&leaves[..size], *policy);
+                        merkle_tree.parallel = *parallel;
+                        merkle_tree.extend(&leaves[size..]).unwrap();
+                        let expected_tree = build_with_policy(&leaves, *policy);
+                        assert_eq!(expected_tree.layers, merkle_tree.layers);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extend_before_build() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree {
+            format: SerializationFormat::Json,
+            ..MerkleTree::default()
+        };
+        merkle_tree.extend(&["a", "b", "c"]).unwrap();
+        assert!(!merkle_tree.builded);
+        let mut expected_tree: MerkleTree = MerkleTree::from(&["a", "b", "c"], SerializationFormat::Json).unwrap();
+        assert_eq!(expected_tree.build(), merkle_tree.build());
+    }
+
+    #[test]
+    fn extend_is_atomic() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b"], SerializationFormat::Json).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let bad_record = bad_record();
+        let good_record = BTreeMap::new();
+        match merkle_tree.extend(&[good_record, bad_record]) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(2, merkle_tree.layers[0].len());
+        assert_eq!(Ok(root), merkle_tree.get_merkle_root());
+    }
+
+    #[test]
+    fn push_batch_matches_push_hash() {
+        let _ = env_logger::init();
+        let hashes: Vec<[u8; 32]> = (0..37u8).map(|index| [index; 32]).collect();
+        let mut merkle_tree_batch: MerkleTree = MerkleTree::default();
+        let mut merkle_tree_push: MerkleTree = MerkleTree::default();
+        merkle_tree_batch.push_hash(hashes[0]);
+        merkle_tree_push.push_hash(hashes[0]);
+        merkle_tree_batch.build().unwrap();
+        merkle_tree_push.build().unwrap();
+        merkle_tree_batch.push_batch(&hashes[1..]);
+        for hash in &hashes[1..] {
+            merkle_tree_push.push_hash(*hash);
+        }
+        assert_eq!(merkle_tree_push.layers, merkle_tree_batch.layers);
+    }
+
+    #[test]
+    fn from_parallel_and_sequential_agree() {
+        let _ = env_logger::init();
+        for size in 1..40usize {
+            let leaves: Vec<usize> = (0..size).collect();
+            let mut merkle_tree_parallel = MerkleTree::from_with_parallel(&leaves,
+                                                                          SerializationFormat::Bincode,
+                                                                          Sha256::new(),
+                                                                          true).unwrap();
+            let mut merkle_tree_sequence = MerkleTree::from_with_parallel(&leaves,
+                                                                          SerializationFormat::Bincode,
+                                                                          Sha256::new(),
+                                                                          false).unwrap();
+            assert!(merkle_tree_parallel.parallel);
+            assert!(!merkle_tree_sequence.parallel);
+            assert_eq!(merkle_tree_parallel.layers[0], merkle_tree_sequence.layers[0]);
+            assert_eq!(merkle_tree_parallel.build(), merkle_tree_sequence.build());
+        }
+        let bad_record = bad_record();
+        for parallel in [false, true].iter() {
+            match MerkleTree::from_with_parallel(&[BTreeMap::new(), bad_record.clone()],
+                                                 SerializationFormat::Json,
+                                                 Sha256::new(),
+                                                 *parallel) {
+                Err(MerkleError::Serialization(_)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_threshold_does_not_change_tree() {
+        let _ = env_logger::init();
+        let leaves: Vec<usize> = (0..1000).collect();
+        let mut expected_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+        expected_tree.parallel = false;
+        expected_tree.build().unwrap();
+        for min_parallel_layer_size in [0, 1, 7, 500, usize::MAX].iter() {
+            let mut merkle_tree = MerkleTree::from(&leaves[..900], SerializationFormat::Bincode).unwrap();
+            merkle_tree.min_parallel_layer_size = *min_parallel_layer_size;
+            merkle_tree.build().unwrap();
+            merkle_tree.extend(&leaves[900..]).unwrap();
+            assert_eq!(expected_tree.layers, merkle_tree.layers);
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn build_in_custom_thread_pool() {
+        let _ = env_logger::init();
+        let thread_pool = Arc::new(ThreadPool::new(::rayon::Configuration::new().num_threads(2)).unwrap());
+        let leaves: Vec<usize> = (0..3000).collect();
+        let mut expected_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+        expected_tree.parallel = false;
+        let expected_root = expected_tree.build().unwrap();
+        let mut merkle_tree = MerkleTree {
+            format: SerializationFormat::Bincode,
+            thread_pool: Some(thread_pool.clone()),
+            min_parallel_layer_size: 2,
+            ..MerkleTree::default()
+        };
+        merkle_tree.extend(&leaves[..2000]).unwrap();
+        merkle_tree.build().unwrap();
+        merkle_tree.extend(&leaves[2000..]).unwrap();
+        assert_eq!(Ok(expected_root), merkle_tree.get_merkle_root());
+        assert_eq!(expected_tree, {
+            merkle_tree.thread_pool = None;
+            merkle_tree.min_parallel_layer_size = DEFAULT_MIN_PARALLEL_LAYER_SIZE;
+            merkle_tree.parallel = false;
+            merkle_tree
+        });
     }
 
     #[test]
     fn test_transaction_hash_not_found() {
-        let mut merkle_tree: MerkleTree = MerkleTree::from(&mut ["a", "b", "c", "d", "e"],
-                                                           SerializationFormat::Json);
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                                           SerializationFormat::Json).unwrap();
         merkle_tree.print();
         merkle_tree.build().unwrap();
         let proof_path = merkle_tree.audit_proof(&[172, 124, 131, 66, 187, 178, 54, 45, 19, 240, 165, 89, 163, 98, 27, 180, 7, 1, 19, 104, 137, 81, 100, 182, 40, 165, 79, 127, 195, 63, 196, 60]);
-        assert_eq!(Err("Transaction hash not found in leaves layer."), proof_path);
+        assert_eq!(Err(MerkleError::LeafNotFound), proof_path);
     }
 
     #[test]
     fn test_root_hash_not_found() {
         let merkle_tree: MerkleTree = MerkleTree::default();
-        assert_eq!(Err("Root hash not found"), merkle_tree.get_merkle_root());
+        assert_eq!(Err(MerkleError::EmptyTree), merkle_tree.get_merkle_root());
     }
 
     #[test]
     fn test_tree_not_builded() {
         let mut merkle_tree: MerkleTree = MerkleTree::default();
-        assert_eq!(Err("Tree not builded"),
+        assert_eq!(Err(MerkleError::NotBuilt),
             merkle_tree.audit_proof(&[172, 124, 131, 66, 187, 178, 54, 45, 19, 240, 165, 89, 163, 98, 27, 180, 7, 1, 19, 104, 137, 81, 100, 182, 40, 165, 79, 127, 195, 63, 196, 60]));
     }
 
@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Read};
+use serde::Serialize;
+use crypto::sha2::Sha256;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
+use serialization_format::SerializationFormat;
+use merkle_error::MerkleError;
+use proof::{Position, Proof, ProofNode};
+use hash_function::{hash_leaf, hash_node, hash_lonely, hash_single};
+
+/// Узел правого края дерева вместе с индексами отслеживаемых "листьев", предком которых он является.
+#[derive(Clone)]
+struct FrontierNode<N> {
+    hash: N,
+    tracked: Vec<usize>,
+}
+
+/// Пути к корню отслеживаемых "листьев", накопленные по мере объединения узлов.
+type TrackedPaths<N> = BTreeMap<usize, Vec<ProofNode<N>>>;
+
+/// Потоковое построение Merkle tree без хранения всех "листьев".
+///
+/// "Листья" добавляются по одному, при этом хранится только правый край дерева - не больше одного
+/// узла на каждом уровне, т.е. O(log n) узлов. Корень совпадает с корнем `MerkleTree`, построенного
+/// на тех же данных с теми же `mode` и `odd_node_policy`. Для заранее выбранных "листьев" можно
+/// получить доказательства включения: их пути накапливаются по ходу добавления.
+pub struct MerkleTreeBuilder<D: Hasher = Sha256> {
+    /// Формат сериализации транзакций перед тем как их хэшировать.
+    pub format: SerializationFormat,
+    /// Экземпляр хэш-функции, который клонируется при хэшировании каждого узла.
+    pub digest: D,
+    /// Режим хэширования узлов. Меняется только до добавления "листьев".
+    pub mode: TreeMode,
+    /// Правило обработки узла, которому не нашлось пары в конце слоя.
+    pub odd_node_policy: OddNodePolicy,
+    frontier: Vec<Option<FrontierNode<D::Node>>>,
+    len: usize,
+    tracked: TrackedPaths<D::Node>,
+}
+
+impl Default for MerkleTreeBuilder<Sha256> {
+    fn default() -> MerkleTreeBuilder<Sha256> {
+        MerkleTreeBuilder::with_digest(Sha256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for MerkleTreeBuilder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleTreeBuilder")
+            .field("format", &self.format)
+            .field("mode", &self.mode)
+            .field("odd_node_policy", &self.odd_node_policy)
+            .field("len", &self.len)
+            .field("tracked", &self.tracked.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<D: Hasher> MerkleTreeBuilder<D> {
+    /// Создаёт пустой построитель, использующий переданную хэш-функцию.
+    pub fn with_digest(digest: D) -> MerkleTreeBuilder<D> {
+        MerkleTreeBuilder {
+            format: SerializationFormat::MsgPack,
+            digest,
+            mode: TreeMode::Classic,
+            odd_node_policy: OddNodePolicy::Rehash,
+            frontier: Vec::new(),
+            len: 0,
+            tracked: BTreeMap::new(),
+        }
+    }
+
+    /// Количество добавленных "листьев".
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Проверяет, были ли добавлены "листья".
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Отмечает "лист" с переданным индексом, чтобы получить для него доказательство включения.
+    /// Отмечать можно только ещё не добавленные "листья": возвращает `false`, если "лист" с таким
+    /// индексом уже был добавлен.
+    pub fn track(&mut self, index: usize) -> bool {
+        if index < self.len {
+            return false;
+        }
+        self.tracked.entry(index).or_default();
+        true
+    }
+
+    /// Сериализует транзакцию, хэширует её и добавляет как очередной "лист".
+    ///
+    /// # Failures
+    /// В случае, если транзакцию не удалось сериализовать возвращается `MerkleError::Serialization`.
+    pub fn push<Serializable>(&mut self, value: &Serializable) -> Result<(), MerkleError>
+        where Serializable: Serialize
+    {
+        let serialized_value = self.format.serialize(value)?;
+        self.push_raw(&serialized_value);
+        Ok(())
+    }
+
+    /// Добавляет как очередной "лист" хэш переданных байт без предварительной сериализации.
+    pub fn push_raw(&mut self, data: &[u8]) {
+        let hashed_data = hash_leaf(&self.digest, self.mode, data);
+        self.push_hash(hashed_data);
+    }
+
+    /// Добавляет как очередной "лист" уже вычисленный хэш.
+    pub fn push_hash(&mut self, hash: D::Node) {
+        let tracked = if self.tracked.contains_key(&self.len) {
+            vec![self.len]
+        } else {
+            Vec::new()
+        };
+        let mut node = FrontierNode { hash, tracked };
+        let mut level = 0;
+        loop {
+            if self.frontier.len() == level {
+                self.frontier.push(None);
+            }
+            match self.frontier[level].take() {
+                Some(left) => {
+                    node = merge(&self.digest, self.mode, left, node, &mut self.tracked);
+                    level += 1;
+                }
+                None => {
+                    self.frontier[level] = Some(node);
+                    break;
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Добавляет транзакции из итератора, сериализуя каждую.
+    ///
+    /// # Failures
+    /// В случае, если транзакцию не удалось сериализовать возвращается `MerkleError::Serialization`.
+    /// Транзакции, предшествующие ошибочной, остаются добавленными.
+    pub fn extend<Iter, Serializable>(&mut self, values: Iter) -> Result<(), MerkleError>
+        where Iter: IntoIterator<Item = Serializable>,
+              Serializable: Serialize
+    {
+        for value in values {
+            self.push(&value)?;
+        }
+        Ok(())
+    }
+
+    /// Читает источник построчно и добавляет каждую строку без завершающего перевода строки
+    /// как "лист" (см. `push_raw`). Возвращает количество добавленных "листьев".
+    ///
+    /// # Failures
+    /// В случае ошибки чтения возвращается `MerkleError::Io`.
+    pub fn push_lines<Reader: BufRead>(&mut self, mut reader: Reader) -> Result<usize, MerkleError> {
+        let mut line = Vec::new();
+        let mut count = 0;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)
+                .map_err(|error| MerkleError::Io(error.to_string()))?;
+            if read == 0 {
+                return Ok(count);
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            self.push_raw(&line);
+            count += 1;
+        }
+    }
+
+    /// Читает источник блоками по `chunk_size` байт и добавляет каждый блок как "лист"
+    /// (см. `push_raw`). Последний блок может быть короче. Возвращает количество добавленных "листьев".
+    ///
+    /// # Failures
+    /// В случае ошибки чтения возвращается `MerkleError::Io`.
+    pub fn push_chunks<Reader: Read>(&mut self,
+                                     mut reader: Reader,
+                                     chunk_size: usize)
+                                     -> Result<usize, MerkleError> {
+        let mut chunk = vec![0; chunk_size.max(1)];
+        let mut count = 0;
+        loop {
+            let mut filled = 0;
+            while filled < chunk.len() {
+                let read = reader.read(&mut chunk[filled..])
+                    .map_err(|error| MerkleError::Io(error.to_string()))?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+            if filled == 0 {
+                return Ok(count);
+            }
+            self.push_raw(&chunk[..filled]);
+            count += 1;
+        }
+    }
+
+    /// Возвращает merkle root hash для добавленных к этому моменту "листьев".
+    /// Вычисление занимает O(log n) и не мешает добавлению последующих "листьев".
+    ///
+    /// # Failures
+    /// В случае, если не было добавлено ни одного "листа" возвращается `MerkleError::EmptyTree`.
+    pub fn root(&self) -> Result<D::Node, MerkleError> {
+        self.fold().map(|(root, _)| root)
+    }
+
+    /// Возвращает доказательства включения отмеченных через `track` "листьев" в дерево из
+    /// добавленных к этому моменту "листьев", в порядке возрастания индексов. Отмеченные, но ещё
+    /// не добавленные "листья" пропускаются.
+    ///
+    /// # Failures
+    /// В случае, если не было добавлено ни одного "листа" возвращается `MerkleError::EmptyTree`.
+    pub fn proofs(&self) -> Result<Vec<Proof<D>>, MerkleError> {
+        let (_, paths) = self.fold()?;
+        Ok(paths.into_iter()
+            .filter(|&(leaf_index, _)| leaf_index < self.len)
+            .map(|(leaf_index, path)| {
+                Proof {
+                    leaf_index,
+                    tree_size: self.len,
+                    path,
+                    digest: self.digest.clone(),
+                    mode: self.mode,
+                    odd_node_policy: self.odd_node_policy,
+                }
+            })
+            .collect())
+    }
+
+    /// Достраивает правый край дерева до корня так же, как это делает `MerkleTree::build`:
+    /// на каждом слое последний неполный узел объединяется с соседом, либо обрабатывается
+    /// правилом `odd_node_policy`.
+    fn fold(&self) -> Result<(D::Node, TrackedPaths<D::Node>), MerkleError> {
+        let mut paths = self.tracked.clone();
+        if self.len == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if self.len == 1 {
+            let leaf = self.frontier[0].as_ref().expect("single leaf is kept at level 0");
+            let root = hash_single(&self.digest, self.mode, self.odd_node_policy, &leaf.hash);
+            return Ok((root, paths));
+        }
+        let mut carry: Option<FrontierNode<D::Node>> = None;
+        let mut layer_size = self.len;
+        let mut level = 0;
+        while layer_size > 1 {
+            let pending = self.frontier.get(level).and_then(|node| node.clone());
+            carry = match (pending, carry) {
+                (Some(left), Some(right)) => {
+                    Some(merge(&self.digest, self.mode, left, right, &mut paths))
+                }
+                (Some(node), None) | (None, Some(node)) => {
+                    Some(FrontierNode {
+                        hash: hash_lonely(&self.digest, self.mode, self.odd_node_policy, &node.hash),
+                        tracked: node.tracked,
+                    })
+                }
+                (None, None) => None,
+            };
+            layer_size = layer_size.div_ceil(2);
+            level += 1;
+        }
+        let top = self.frontier.get(level).and_then(|node| node.clone());
+        match top.or(carry) {
+            Some(root) => Ok((root.hash, paths)),
+            None => Err(MerkleError::EmptyTree),
+        }
+    }
+}
+
+/// Объединяет два соседних узла, дописывая в пути отслеживаемых "листьев" соседний узел.
+fn merge<D: Hasher>(digest: &D,
+                    mode: TreeMode,
+                    left: FrontierNode<D::Node>,
+                    right: FrontierNode<D::Node>,
+                    paths: &mut TrackedPaths<D::Node>)
+                    -> FrontierNode<D::Node> {
+    for leaf_index in &left.tracked {
+        if let Some(path) = paths.get_mut(leaf_index) {
+            path.push(ProofNode { hash: right.hash, position: Position::Right });
+        }
+    }
+    for leaf_index in &right.tracked {
+        if let Some(path) = paths.get_mut(leaf_index) {
+            path.push(ProofNode { hash: left.hash, position: Position::Left });
+        }
+    }
+    let mut tracked = left.tracked;
+    tracked.extend(right.tracked);
+    FrontierNode {
+        hash: hash_node(digest, mode, &left.hash, &right.hash),
+        tracked,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate env_logger;
+    use super::MerkleTreeBuilder;
+    use merkle_tree::MerkleTree;
+    use merkle_error::MerkleError;
+    use serialization_format::SerializationFormat;
+    use odd_node_policy::OddNodePolicy;
+    use tree_mode::TreeMode;
+    use test_helpers::{ODD_NODE_POLICIES, bad_record};
+
+    #[test]
+    fn same_root_as_build() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            let mut builder = MerkleTreeBuilder {
+                format: SerializationFormat::Bincode,
+                odd_node_policy: *policy,
+                ..MerkleTreeBuilder::default()
+            };
+            for size in 1..70usize {
+                builder.push(&(size - 1)).unwrap();
+                let leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                merkle_tree.odd_node_policy = *policy;
+                assert_eq!(merkle_tree.build(), builder.root());
+                assert_eq!(size, builder.len());
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_for_tracked_leaves() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..20usize {
+                let mut builder = MerkleTreeBuilder {
+                    format: SerializationFormat::Bincode,
+                    odd_node_policy: *policy,
+                    ..MerkleTreeBuilder::default()
+                };
+                let tracked: Vec<usize> = (0..size + 2).filter(|index| index % 3 != 1).collect();
+                for index in &tracked {
+                    assert!(builder.track(*index));
+                }
+                builder.extend(0..size).unwrap();
+                assert!(!builder.track(0));
+                let leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                merkle_tree.odd_node_policy = *policy;
+                let root = merkle_tree.build().unwrap();
+                let proofs = builder.proofs().unwrap();
+                let expected_indices: Vec<usize> = tracked.into_iter().filter(|&index| index < size).collect();
+                assert_eq!(expected_indices, proofs.iter().map(|proof| proof.leaf_index).collect::<Vec<_>>());
+                for proof in proofs {
+                    assert_eq!(merkle_tree.proof_by_index(proof.leaf_index), Ok(proof.clone()));
+                    assert!(proof.verify_value(&root, &proof.leaf_index, &SerializationFormat::Bincode));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rfc6962_lines() {
+        let _ = env_logger::init();
+        let log = b"first record\nsecond record\n\nfourth record";
+        let mut builder = MerkleTreeBuilder {
+            mode: TreeMode::Rfc6962,
+            odd_node_policy: OddNodePolicy::Promote,
+            ..MerkleTreeBuilder::default()
+        };
+        assert_eq!(4, builder.push_lines(&log[..]).unwrap());
+        let mut merkle_tree = MerkleTree::rfc6962();
+        for line in log.split(|byte| *byte == b'\n') {
+            merkle_tree.push_raw(line);
+        }
+        assert_eq!(merkle_tree.build(), builder.root());
+    }
+
+    #[test]
+    fn chunks() {
+        let _ = env_logger::init();
+        let data: Vec<u8> = (0..100u8).collect();
+        let mut builder = MerkleTreeBuilder::default();
+        assert_eq!(7, builder.push_chunks(&data[..], 16).unwrap());
+        let mut merkle_tree = MerkleTree::default();
+        for chunk in data.chunks(16) {
+            merkle_tree.push_raw(chunk);
+        }
+        assert_eq!(merkle_tree.build(), builder.root());
+    }
+
+    #[test]
+    fn builder_failures() {
+        let _ = env_logger::init();
+        let mut builder = MerkleTreeBuilder::default();
+        assert!(builder.is_empty());
+        assert_eq!(Err(MerkleError::EmptyTree), builder.root());
+        assert_eq!(Err(MerkleError::EmptyTree), builder.proofs().map(|_| ()));
+        builder.format = SerializationFormat::Json;
+        let bad_record = bad_record();
+        match builder.push(&bad_record) {
+            Err(MerkleError::Serialization(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(builder.is_empty());
+    }
+}
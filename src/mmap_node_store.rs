@@ -0,0 +1,255 @@
+use std::fs::File;
+use std::io::Seek;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use libc;
+use hasher::{Hasher, Node};
+use merkle_tree::MerkleTree;
+use node_store::{NodeStore, check_get, node_size, io_error};
+use persistence::TreeHeader;
+use merkle_error::MerkleError;
+
+/// Хранилище узлов только для чтения, отображающее в память файл дерева, сохранённого методом
+/// `MerkleTree::save`: заголовок, за которым слои лежат подряд, начиная со слоя "листьев".
+/// Узлы читаются прямо из отображения, поэтому открытие не зависит от размера дерева, а в памяти
+/// процесса находятся только страницы файла, к которым было обращение.
+///
+/// Файл отображается как `MAP_PRIVATE` с `PROT_READ`: через отображение его нельзя изменить, а
+/// узлы копируются из него в `get_batch`, и ссылки на отображённую память наружу не выдаются.
+/// Изменения файла другими процессами могут оказаться видны в ещё не прочитанных страницах,
+/// поэтому файл не должен изменяться, пока хранилище открыто.
+pub struct MmapNodeStore<N: Node> {
+    header: TreeHeader,
+    data: *const u8,
+    data_len: usize,
+    nodes_offset: usize,
+    layer_offsets: Vec<usize>,
+    node: PhantomData<N>,
+}
+
+// SAFETY: `data` указывает на отображение, которым владеет только это хранилище: оно создаётся в
+// `open`, освобождается в `drop` и доступно только для чтения, поэтому его можно освободить из
+// любого потока.
+unsafe impl<N: Node> Send for MmapNodeStore<N> {}
+
+// SAFETY: через `&MmapNodeStore` отображение только читается, а хранилище не имеет внутренней
+// изменяемости, поэтому одновременное чтение из нескольких потоков не приводит к гонкам.
+unsafe impl<N: Node> Sync for MmapNodeStore<N> {}
+
+impl<N: Node> MmapNodeStore<N> {
+    /// Открывает файл дерева и отображает его в память.
+    ///
+    /// # Failures
+    /// Возвращает `MerkleError::Deserialization`, если файл не является сохранённым деревом, его
+    /// размер не совпадает с указанным в заголовке или размер узла не совпадает с `N`, и
+    /// `MerkleError::Io` при ошибке чтения или отображения файла.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapNodeStore<N>, MerkleError> {
+        let mut file = File::open(path).map_err(io_error)?;
+        let header = TreeHeader::read(&mut file)?;
+        let node_size = node_size::<N>();
+        if header.node_size as usize != node_size {
+            return Err(MerkleError::Deserialization(format!("Node size {} does not match {}",
+                                                            header.node_size,
+                                                            node_size)));
+        }
+        let nodes_offset = file.stream_position().map_err(io_error)? as usize;
+        let mut layer_offsets = Vec::with_capacity(header.layer_lengths.len());
+        let mut data_len = nodes_offset;
+        for &layer_len in &header.layer_lengths {
+            layer_offsets.push(data_len);
+            data_len = (layer_len as usize)
+                .checked_mul(node_size)
+                .and_then(|layer_size| layer_size.checked_add(data_len))
+                .ok_or_else(|| MerkleError::Deserialization("Layer lengths overflow".to_string()))?;
+        }
+        let file_len = file.metadata().map_err(io_error)?.len();
+        if file_len != data_len as u64 {
+            return Err(MerkleError::Deserialization(format!("File size {} does not match {} \
+                                                             expected from header",
+                                                            file_len,
+                                                            data_len)));
+        }
+        // SAFETY: файл открыт и имеет размер `data_len`, отображение создаётся только для чтения.
+        let data = unsafe {
+            libc::mmap(ptr::null_mut(),
+                       data_len,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE,
+                       ::std::os::unix::io::AsRawFd::as_raw_fd(&file),
+                       0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io_error(::std::io::Error::last_os_error()));
+        }
+        Ok(MmapNodeStore {
+            header,
+            data: data as *const u8,
+            data_len,
+            nodes_offset,
+            layer_offsets,
+            node: PhantomData,
+        })
+    }
+
+    /// Заголовок файла дерева.
+    pub fn header(&self) -> &TreeHeader {
+        &self.header
+    }
+
+    /// Байты узлов всех слоёв, следующие за заголовком.
+    fn nodes(&self) -> &[u8] {
+        // SAFETY: `data` - отображение размером `data_len`, живущее до `drop`.
+        let data = unsafe { slice::from_raw_parts(self.data, self.data_len) };
+        &data[self.nodes_offset..]
+    }
+}
+
+impl<N: Node> Drop for MmapNodeStore<N> {
+    fn drop(&mut self) {
+        // SAFETY: отображение создано в `open` и больше нигде не освобождается.
+        unsafe {
+            libc::munmap(self.data as *mut libc::c_void, self.data_len);
+        }
+    }
+}
+
+impl<N: Node> NodeStore<N> for MmapNodeStore<N> {
+    fn layers_count(&self) -> usize {
+        self.header.layer_lengths.len()
+    }
+
+    fn layer_len(&self, layer: usize) -> usize {
+        self.header.layer_lengths.get(layer).map_or(0, |&layer_len| layer_len as usize)
+    }
+
+    fn get(&self, layer: usize, index: usize) -> Result<N, MerkleError> {
+        Ok(self.get_batch(layer, index, 1)?[0])
+    }
+
+    fn put(&mut self, _layer: usize, _index: usize, _node: N) -> Result<(), MerkleError> {
+        Err(MerkleError::ReadOnly)
+    }
+
+    fn get_batch(&self, layer: usize, index: usize, count: usize) -> Result<Vec<N>, MerkleError> {
+        check_get(self.layer_len(layer), index, count)?;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let node_size = node_size::<N>();
+        let start = self.layer_offsets[layer] - self.nodes_offset + index * node_size;
+        Ok(self.nodes()[start..start + count * node_size]
+            .chunks(node_size)
+            .map(|chunk| {
+                let mut node = N::zeroed();
+                node.as_mut().copy_from_slice(chunk);
+                node
+            })
+            .collect())
+    }
+
+    fn put_batch(&mut self, _layer: usize, _index: usize, _nodes: &[N]) -> Result<(), MerkleError> {
+        Err(MerkleError::ReadOnly)
+    }
+}
+
+impl<D> MerkleTree<D, MmapNodeStore<D::Node>>
+    where D: Hasher
+{
+    /// Открывает дерево, сохранённое методом `save`, отображая файл в память. Настройки дерева
+    /// берутся из заголовка файла, хэш-функция передаётся в `digest`. Корень и доказательства
+    /// включения (`get_merkle_root`, `proof_by_index`) вычисляются прямо из отображения без
+    /// загрузки слоёв; изменять такое дерево нельзя.
+    ///
+    /// # Failures
+    /// Смотри `MmapNodeStore::open`.
+    pub fn open_mmap<P: AsRef<Path>>(path: P, digest: D) -> Result<MerkleTree<D, MmapNodeStore<D::Node>>, MerkleError> {
+        let store: MmapNodeStore<D::Node> = MmapNodeStore::open(path)?;
+        let header = store.header().clone();
+        Ok(header.tree(digest, store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use std::fs::{self, File, OpenOptions};
+    use crypto::sha2::{Sha256, Sha512};
+    use super::MmapNodeStore;
+    use merkle_tree::MerkleTree;
+    use node_store::NodeStore;
+    use merkle_error::MerkleError;
+    use test_helpers::{ODD_NODE_POLICIES, temp_path};
+
+    #[test]
+    fn open_saved_tree() {
+        let _ = env_logger::init();
+        let path = temp_path("open_saved_tree");
+        for policy in ODD_NODE_POLICIES.iter() {
+            for leaves_count in 1..12usize {
+                let mut merkle_tree: MerkleTree = MerkleTree {
+                    odd_node_policy: *policy,
+                    ..MerkleTree::default()
+                };
+                for leaf in 0..leaves_count {
+                    merkle_tree.push(&leaf).unwrap();
+                }
+                merkle_tree.build().unwrap();
+                merkle_tree.save(&mut File::create(&path).unwrap()).unwrap();
+
+                let mapped = MerkleTree::open_mmap(&path, Sha256::new()).unwrap();
+                assert_eq!(*policy, mapped.odd_node_policy);
+                assert_eq!(merkle_tree.get_merkle_root(), mapped.get_merkle_root());
+                for leaf_index in 0..leaves_count {
+                    assert_eq!(merkle_tree.proof_by_index(leaf_index),
+                               mapped.proof_by_index(leaf_index));
+                }
+                assert_eq!(Err(MerkleError::IndexOutOfRange { index: leaves_count, len: leaves_count }),
+                           mapped.proof_by_index(leaves_count));
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mapped_tree_is_read_only() {
+        let _ = env_logger::init();
+        let path = temp_path("mapped_tree_is_read_only");
+        let mut merkle_tree: MerkleTree = MerkleTree::default();
+        merkle_tree.push(&"a").unwrap();
+        merkle_tree.push(&"b").unwrap();
+        merkle_tree.build().unwrap();
+        merkle_tree.save(&mut File::create(&path).unwrap()).unwrap();
+        let mut mapped = MerkleTree::open_mmap(&path, Sha256::new()).unwrap();
+        assert_eq!(Err(MerkleError::ReadOnly), mapped.layers.put(1, 0, [0; 32]));
+        assert_eq!(merkle_tree.get_merkle_root(), mapped.get_merkle_root());
+        assert_eq!(Err(MerkleError::ReadOnly), mapped.append_leaves(&[[0; 32]]));
+        assert_eq!(merkle_tree.get_merkle_root(), mapped.get_merkle_root());
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: usize::MAX, len: 2 }),
+                   mapped.layers.get_batch(0, usize::MAX, 2));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 2, len: 2 }),
+                   mapped.layers.get_batch(0, 1, 2));
+        assert_eq!(Ok(Vec::new()), mapped.layers.get_batch(5, 0, 0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_invalid_file() {
+        let _ = env_logger::init();
+        let path = temp_path("open_invalid_file");
+        let mut merkle_tree: MerkleTree = MerkleTree::default();
+        merkle_tree.push(&"a").unwrap();
+        merkle_tree.build().unwrap();
+        merkle_tree.save(&mut File::create(&path).unwrap()).unwrap();
+        assert!(matches!(MerkleTree::open_mmap(&path, Sha512::new()),
+                         Err(MerkleError::Deserialization(_))));
+
+        let file_len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(file_len - 1).unwrap();
+        assert!(matches!(MmapNodeStore::<[u8; 32]>::open(&path),
+                         Err(MerkleError::Deserialization(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Serialize;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use serialization_format::SerializationFormat;
+use hash_function::{hash_leaf, hash_node};
+use merkle_mountain_range::{bag_peaks, peak_ranges};
+
+/// Доказательство включения "листа" в Merkle Mountain Range определённого размера.
+///
+/// Содержит путь от "листа" до пика его горы и пики остальных гор. Высота горы и положение её пика
+/// среди остальных вычисляются по `leaf_index` и `size`, поэтому доказательство проверяется
+/// относительно корня, который аккумулятор имел при `size` "листьях", независимо от того, сколько
+/// "листьев" было добавлено позже. Корень не содержит размера аккумулятора: одни и те же хэши
+/// "упаковываются" в один корень при разных размерах, поэтому размер проверяющий получает вместе с
+/// корнем, а не из доказательства.
+#[derive(Clone)]
+pub struct MountainRangeProof<D: Hasher> {
+    /// Индекс "листа".
+    pub leaf_index: usize,
+    /// Количество "листьев" в аккумуляторе, относительно корня которого строилось доказательство.
+    pub size: usize,
+    /// Соседние узлы на пути от "листа" к пику его горы.
+    pub path: Vec<D::Node>,
+    /// Пики остальных гор слева направо.
+    pub peaks: Vec<D::Node>,
+    /// Хэш-функция аккумулятора.
+    pub digest: D,
+    /// Режим хэширования узлов.
+    pub mode: TreeMode,
+}
+
+impl_debug_eq!(MountainRangeProof { leaf_index, size, path, peaks, mode });
+
+impl<D: Hasher> MountainRangeProof<D> {
+    /// Вычисляет корень аккумулятора из `size` "листьев" по доказательству для переданного хэша
+    /// "листа". Возвращает `None`, если длина пути или количество пиков не согласуются с
+    /// `leaf_index` и `size`.
+    pub fn root(&self, leaf_hash: &D::Node) -> Option<D::Node> {
+        if self.leaf_index >= self.size {
+            return None;
+        }
+        let ranges = peak_ranges(self.size);
+        if self.peaks.len() + 1 != ranges.len() {
+            return None;
+        }
+        let position = ranges.iter()
+            .position(|&(height, start)| self.leaf_index < start + (1 << height))?;
+        if self.path.len() != ranges[position].0 {
+            return None;
+        }
+        let peak = self.path
+            .iter()
+            .enumerate()
+            .fold(*leaf_hash, |hash, (level, sibling)| if (self.leaf_index >> level) & 1 == 1 {
+                hash_node(&self.digest, self.mode, sibling, &hash)
+            } else {
+                hash_node(&self.digest, self.mode, &hash, sibling)
+            });
+        let mut peaks = self.peaks.clone();
+        peaks.insert(position, peak);
+        Some(bag_peaks(&self.digest, self.mode, &peaks))
+    }
+
+    /// Проверяет, что "лист" с переданным хэшем входит в аккумулятор из `size` "листьев" с корнем
+    /// `root`. Доказательство, построенное для другого размера, не проходит проверку.
+    pub fn verify(&self, root: &D::Node, size: usize, leaf_hash: &D::Node) -> bool {
+        self.size == size && self.root(leaf_hash).as_ref() == Some(root)
+    }
+
+    /// Проверяет, что переданное значение входит в аккумулятор из `size` "листьев" с корнем `root`.
+    /// Значение сериализуется в указанном формате и хэшируется так же, как при добавлении.
+    /// Если значение не удалось сериализовать, оно не может входить в аккумулятор и проверка не
+    /// проходит.
+    pub fn verify_value<Serializable>(&self,
+                                      root: &D::Node,
+                                      size: usize,
+                                      value: &Serializable,
+                                      format: &SerializationFormat)
+                                      -> bool
+        where Serializable: Serialize
+    {
+        match format.serialize(value) {
+            Ok(serialized_value) => {
+                let leaf_hash = hash_leaf(&self.digest, self.mode, &serialized_value);
+                self.verify(root, size, &leaf_hash)
+            }
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
+use hash_function::{hash_node, hash_lonely, hash_single};
+
+/// Доказательство включения сразу нескольких "листьев" (multiproof).
+///
+/// В отличие от набора отдельных `Proof` не содержит узлов, которые верификатор может вычислить
+/// сам из проверяемых "листьев": на каждом слое в `nodes` попадают только соседи, не являющиеся
+/// предками ни одного из доказываемых "листьев".
+#[derive(Clone)]
+pub struct MultiProof<D: Hasher> {
+    /// Индексы доказываемых "листьев" в 0-ом слое дерева в порядке возрастания, без повторов.
+    pub leaf_indices: Vec<usize>,
+    /// Количество "листьев" в дереве на момент построения доказательства.
+    pub tree_size: usize,
+    /// Недостающие узлы слой за слоем, начиная со слоя "листьев", слева направо.
+    pub nodes: Vec<D::Node>,
+    /// Хэш-функция дерева.
+    pub digest: D,
+    /// Режим хэширования узлов дерева.
+    pub mode: TreeMode,
+    /// Правило обработки узла, которому не нашлось пары в конце слоя.
+    pub odd_node_policy: OddNodePolicy,
+}
+
+impl_debug_eq!(MultiProof { leaf_indices, tree_size, nodes, mode, odd_node_policy });
+
+impl<D: Hasher> MultiProof<D> {
+    /// Вычисляет корень дерева по доказательству для хэшей "листьев", переданных в порядке
+    /// `leaf_indices`. Возвращает `None`, если количество хэшей не совпадает с количеством индексов,
+    /// индексы не упорядочены или выходят за пределы дерева, либо узлов в доказательстве не хватает
+    /// или остались лишние.
+    pub fn root(&self, leaf_hashes: &[D::Node]) -> Option<D::Node> {
+        if self.leaf_indices.is_empty() || leaf_hashes.len() != self.leaf_indices.len() {
+            return None;
+        }
+        if self.leaf_indices.windows(2).any(|pair| pair[0] >= pair[1]) ||
+           self.leaf_indices[self.leaf_indices.len() - 1] >= self.tree_size {
+            return None;
+        }
+        if self.tree_size == 1 {
+            return if self.nodes.is_empty() {
+                Some(hash_single(&self.digest, self.mode, self.odd_node_policy, &leaf_hashes[0]))
+            } else {
+                None
+            };
+        }
+        let mut nodes = self.nodes.iter();
+        let mut known: Vec<(usize, D::Node)> = self.leaf_indices
+            .iter()
+            .cloned()
+            .zip(leaf_hashes.iter().cloned())
+            .collect();
+        let mut layer_size = self.tree_size;
+        while layer_size > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut position = 0;
+            while position < known.len() {
+                let (index, hash) = known[position];
+                let parent = if !index.is_multiple_of(2) {
+                    let sibling = nodes.next()?;
+                    hash_node(&self.digest, self.mode, sibling, &hash)
+                } else if index + 1 < layer_size {
+                    if position + 1 < known.len() && known[position + 1].0 == index + 1 {
+                        position += 1;
+                        hash_node(&self.digest, self.mode, &hash, &known[position].1)
+                    } else {
+                        let sibling = nodes.next()?;
+                        hash_node(&self.digest, self.mode, &hash, sibling)
+                    }
+                } else {
+                    hash_lonely(&self.digest, self.mode, self.odd_node_policy, &hash)
+                };
+                parents.push((index / 2, parent));
+                position += 1;
+            }
+            known = parents;
+            layer_size = layer_size.div_ceil(2);
+        }
+        match nodes.next() {
+            Some(_) => None,
+            None => Some(known[0].1),
+        }
+    }
+
+    /// Проверяет, что "листья" с переданными хэшами (в порядке `leaf_indices`) входят в дерево
+    /// с корнем `root`. Для проверки не требуется само дерево.
+    pub fn verify(&self, root: &D::Node, leaf_hashes: &[D::Node]) -> bool {
+        self.root(leaf_hashes).as_ref() == Some(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate env_logger;
+    use merkle_tree::MerkleTree;
+    use serialization_format::SerializationFormat;
+    use merkle_error::MerkleError;
+    use test_helpers::ODD_NODE_POLICIES;
+
+    #[test]
+    fn verify_all_subsets() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..10usize {
+                let leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                merkle_tree.odd_node_policy = *policy;
+                let root = merkle_tree.build().unwrap();
+                for mask in 1..(1usize << size) {
+                    let indices: Vec<usize> = (0..size).filter(|index| mask & (1 << index) != 0).collect();
+                    let leaf_hashes: Vec<[u8; 32]> = indices.iter()
+                        .map(|&index| merkle_tree.layers[0][index])
+                        .collect();
+                    let proof = merkle_tree.multi_proof(&indices).unwrap();
+                    assert!(proof.verify(&root, &leaf_hashes));
+                    let mut tampered = leaf_hashes.clone();
+                    tampered[0][0] ^= 1;
+                    assert!(!proof.verify(&root, &tampered));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shares_interior_nodes() {
+        let _ = env_logger::init();
+        let leaves: Vec<usize> = (0..16).collect();
+        let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let proof = merkle_tree.multi_proof(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(vec![merkle_tree.layers[2][1], merkle_tree.layers[3][1]], proof.nodes);
+        let leaf_hashes = merkle_tree.layers[0][..4].to_vec();
+        assert!(proof.verify(&root, &leaf_hashes));
+        let all: Vec<usize> = (0..16).collect();
+        let proof = merkle_tree.multi_proof(&all).unwrap();
+        assert!(proof.nodes.is_empty());
+        assert!(proof.verify(&root, &merkle_tree.layers[0].clone()));
+    }
+
+    #[test]
+    fn unsorted_and_duplicate_indices() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from(&["a", "b", "c", "d", "e"], SerializationFormat::Json).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let proof = merkle_tree.multi_proof(&[4, 1, 4]).unwrap();
+        assert_eq!(vec![1, 4], proof.leaf_indices);
+        let leaf_hashes = [merkle_tree.layers[0][1], merkle_tree.layers[0][4]];
+        assert!(proof.verify(&root, &leaf_hashes));
+        assert!(!proof.verify(&root, &[leaf_hashes[1], leaf_hashes[0]]));
+        assert!(!proof.verify(&root, &leaf_hashes[..1]));
+    }
+
+    #[test]
+    fn reject_malformed_proof() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from(&["a", "b", "c", "d", "e"], SerializationFormat::Json).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let proof = merkle_tree.multi_proof(&[0, 3]).unwrap();
+        let leaf_hashes = [merkle_tree.layers[0][0], merkle_tree.layers[0][3]];
+        let mut short_nodes = proof.clone();
+        short_nodes.nodes.pop();
+        assert_eq!(None, short_nodes.root(&leaf_hashes));
+        let mut long_nodes = proof.clone();
+        long_nodes.nodes.push(root);
+        assert_eq!(None, long_nodes.root(&leaf_hashes));
+        let mut wrong_indices = proof.clone();
+        wrong_indices.leaf_indices = vec![3, 0];
+        assert_eq!(None, wrong_indices.root(&leaf_hashes));
+        let mut out_of_range = proof.clone();
+        out_of_range.leaf_indices = vec![0, 5];
+        assert_eq!(None, out_of_range.root(&leaf_hashes));
+    }
+
+    #[test]
+    fn multi_proof_failures() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from(&["a", "b", "c"], SerializationFormat::Json).unwrap();
+        assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.multi_proof(&[0]));
+        merkle_tree.build().unwrap();
+        assert_eq!(Err(MerkleError::NoLeafIndices), merkle_tree.multi_proof(&[]));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+                   merkle_tree.multi_proof(&[0, 3]));
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use hasher::Node;
+use merkle_error::MerkleError;
+
+/// Хранилище узлов дерева, адресуемых индексом слоя и индексом узла в слое.
+///
+/// Слои заполняются последовательно: узел можно записать по индексу, не превышающему текущий
+/// размер слоя (запись по индексу равному размеру добавляет узел в конец), а новый слой можно
+/// начать только сразу над последним. Реализация по умолчанию - `Vec<Vec<N>>`, в которой дерево
+/// целиком хранится в памяти; `FileNodeStore` хранит слои в файлах.
+pub trait NodeStore<N: Node> {
+    /// Количество слоёв в хранилище.
+    fn layers_count(&self) -> usize;
+
+    /// Количество узлов в слое. Для отсутствующего слоя возвращает 0.
+    fn layer_len(&self, layer: usize) -> usize;
+
+    /// Возвращает узел.
+    ///
+    /// # Failures
+    /// В случае, если узла нет в хранилище возвращает `MerkleError::IndexOutOfRange`.
+    fn get(&self, layer: usize, index: usize) -> Result<N, MerkleError>;
+
+    /// Записывает узел, заменяя существующий, либо добавляя его в конец слоя.
+    ///
+    /// # Failures
+    /// В случае, если индекс больше размера слоя, либо слой не следует сразу за последним,
+    /// возвращает `MerkleError::IndexOutOfRange`.
+    fn put(&mut self, layer: usize, index: usize, node: N) -> Result<(), MerkleError>;
+
+    /// Возвращает `count` узлов слоя, начиная с индекса `index`.
+    fn get_batch(&self, layer: usize, index: usize, count: usize) -> Result<Vec<N>, MerkleError> {
+        check_get(self.layer_len(layer), index, count)?;
+        (index..index + count).map(|node_index| self.get(layer, node_index)).collect()
+    }
+
+    /// Записывает узлы слоя подряд, начиная с индекса `index`.
+    fn put_batch(&mut self, layer: usize, index: usize, nodes: &[N]) -> Result<(), MerkleError> {
+        for (offset, node) in nodes.iter().enumerate() {
+            self.put(layer, index + offset, *node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Проверяет, что `count` узлов начиная с индекса `index` помещаются в слой длиной `layer_len`.
+pub fn check_get(layer_len: usize, index: usize, count: usize) -> Result<(), MerkleError> {
+    match index.checked_add(count) {
+        Some(end) if end <= layer_len => Ok(()),
+        _ => {
+            let last = index.saturating_add(count.saturating_sub(1));
+            Err(MerkleError::IndexOutOfRange { index: last, len: layer_len })
+        }
+    }
+}
+
+/// Проверяет, что узлы можно записать в слой `layer` начиная с индекса `index`.
+fn check_put(layers_count: usize, layer: usize, layer_len: usize, index: usize) -> Result<(), MerkleError> {
+    if layer > layers_count {
+        return Err(MerkleError::IndexOutOfRange { index: layer, len: layers_count });
+    }
+    if index > layer_len {
+        return Err(MerkleError::IndexOutOfRange { index, len: layer_len });
+    }
+    Ok(())
+}
+
+impl<N: Node> NodeStore<N> for Vec<Vec<N>> {
+    fn layers_count(&self) -> usize {
+        self.len()
+    }
+
+    fn layer_len(&self, layer: usize) -> usize {
+        self.as_slice().get(layer).map_or(0, |layer| layer.len())
+    }
+
+    fn get(&self, layer: usize, index: usize) -> Result<N, MerkleError> {
+        self.as_slice()
+            .get(layer)
+            .and_then(|nodes| nodes.as_slice().get(index))
+            .cloned()
+            .ok_or(MerkleError::IndexOutOfRange { index, len: self.layer_len(layer) })
+    }
+
+    fn put(&mut self, layer: usize, index: usize, node: N) -> Result<(), MerkleError> {
+        self.put_batch(layer, index, &[node])
+    }
+
+    fn put_batch(&mut self, layer: usize, index: usize, nodes: &[N]) -> Result<(), MerkleError> {
+        check_put(self.len(), layer, self.layer_len(layer), index)?;
+        if layer == self.len() {
+            self.push(Vec::with_capacity(nodes.len()));
+        }
+        let layer = &mut self[layer];
+        let overwritten = nodes.len().min(layer.len() - index);
+        layer[index..index + overwritten].copy_from_slice(&nodes[..overwritten]);
+        layer.extend_from_slice(&nodes[overwritten..]);
+        Ok(())
+    }
+}
+
+/// Хранилище, в котором каждый слой дерева записан в отдельный файл каталога: узлы лежат подряд
+/// без разделителей, узел с индексом `i` начинается со смещения `i * размер узла`.
+/// В памяти хранятся только дескрипторы файлов и размеры слоёв, поэтому размер дерева
+/// ограничен лишь местом на диске.
+pub struct FileNodeStore<N: Node> {
+    directory: PathBuf,
+    layers: Vec<Mutex<File>>,
+    layer_lens: Vec<usize>,
+    node: PhantomData<N>,
+}
+
+impl<N: Node> FileNodeStore<N> {
+    /// Создаёт пустое хранилище в каталоге `directory`, создавая каталог при необходимости.
+    /// Файлы слоёв, оставшиеся в каталоге от предыдущего хранилища, удаляются.
+    ///
+    /// # Failures
+    /// При ошибке работы с файловой системой возвращает `MerkleError::Io`.
+    pub fn create<P: AsRef<Path>>(directory: P) -> Result<FileNodeStore<N>, MerkleError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(io_error)?;
+        let mut layer = 0;
+        while layer_path(&directory, layer).exists() {
+            fs::remove_file(layer_path(&directory, layer)).map_err(io_error)?;
+            layer += 1;
+        }
+        Ok(FileNodeStore {
+            directory,
+            layers: Vec::new(),
+            layer_lens: Vec::new(),
+            node: PhantomData,
+        })
+    }
+
+    /// Открывает хранилище, ранее созданное в каталоге `directory`.
+    ///
+    /// # Failures
+    /// При ошибке работы с файловой системой возвращает `MerkleError::Io`, если размер файла
+    /// слоя не кратен размеру узла - `MerkleError::Deserialization`.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<FileNodeStore<N>, MerkleError> {
+        let directory = directory.as_ref().to_path_buf();
+        let node_size = node_size::<N>() as u64;
+        let mut layers = Vec::new();
+        let mut layer_lens = Vec::new();
+        while layer_path(&directory, layers.len()).exists() {
+            let path = layer_path(&directory, layers.len());
+            let file = OpenOptions::new().read(true).write(true).open(&path).map_err(io_error)?;
+            let file_len = file.metadata().map_err(io_error)?.len();
+            if !file_len.is_multiple_of(node_size) {
+                return Err(MerkleError::Deserialization(format!("Size of {} is not a multiple of \
+                                                                 node size {}",
+                                                                path.display(),
+                                                                node_size)));
+            }
+            layers.push(Mutex::new(file));
+            layer_lens.push((file_len / node_size) as usize);
+        }
+        Ok(FileNodeStore {
+            directory,
+            layers,
+            layer_lens,
+            node: PhantomData,
+        })
+    }
+
+    /// Каталог хранилища.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Сбрасывает записанные узлы на диск.
+    ///
+    /// # Failures
+    /// При ошибке записи возвращает `MerkleError::Io`.
+    pub fn sync(&self) -> Result<(), MerkleError> {
+        for layer in &self.layers {
+            lock(layer).sync_all().map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<N: Node> NodeStore<N> for FileNodeStore<N> {
+    fn layers_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn layer_len(&self, layer: usize) -> usize {
+        self.layer_lens.get(layer).cloned().unwrap_or(0)
+    }
+
+    fn get(&self, layer: usize, index: usize) -> Result<N, MerkleError> {
+        Ok(self.get_batch(layer, index, 1)?[0])
+    }
+
+    fn put(&mut self, layer: usize, index: usize, node: N) -> Result<(), MerkleError> {
+        self.put_batch(layer, index, &[node])
+    }
+
+    fn get_batch(&self, layer: usize, index: usize, count: usize) -> Result<Vec<N>, MerkleError> {
+        check_get(self.layer_len(layer), index, count)?;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let node_size = node_size::<N>();
+        let mut bytes = vec![0u8; count * node_size];
+        {
+            let mut file = lock(&self.layers[layer]);
+            file.seek(SeekFrom::Start((index * node_size) as u64)).map_err(io_error)?;
+            file.read_exact(&mut bytes).map_err(io_error)?;
+        }
+        Ok(bytes.chunks(node_size)
+            .map(|chunk| {
+                let mut node = N::zeroed();
+                node.as_mut().copy_from_slice(chunk);
+                node
+            })
+            .collect())
+    }
+
+    fn put_batch(&mut self, layer: usize, index: usize, nodes: &[N]) -> Result<(), MerkleError> {
+        check_put(self.layers.len(), layer, self.layer_len(layer), index)?;
+        if layer == self.layers.len() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(layer_path(&self.directory, layer))
+                .map_err(io_error)?;
+            self.layers.push(Mutex::new(file));
+            self.layer_lens.push(0);
+        }
+        let node_size = node_size::<N>();
+        let mut bytes = Vec::with_capacity(nodes.len() * node_size);
+        for node in nodes {
+            bytes.extend_from_slice(node.as_ref());
+        }
+        {
+            let mut file = lock(&self.layers[layer]);
+            file.seek(SeekFrom::Start((index * node_size) as u64)).map_err(io_error)?;
+            file.write_all(&bytes).map_err(io_error)?;
+        }
+        self.layer_lens[layer] = self.layer_lens[layer].max(index + nodes.len());
+        Ok(())
+    }
+}
+
+/// Путь к файлу слоя.
+fn layer_path(directory: &Path, layer: usize) -> PathBuf {
+    directory.join(format!("layer_{}.bin", layer))
+}
+
+/// Размер узла в байтах.
+pub fn node_size<N: Node>() -> usize {
+    N::zeroed().as_ref().len()
+}
+
+/// Захватывает файл слоя. Файл остаётся пригодным, даже если другой поток запаниковал под
+/// блокировкой, так как его позиция выставляется перед каждой операцией.
+fn lock(file: &Mutex<File>) -> ::std::sync::MutexGuard<'_, File> {
+    file.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Преобразует ошибку ввода-вывода в `MerkleError::Io`.
+pub fn io_error(error: ::std::io::Error) -> MerkleError {
+    MerkleError::Io(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use std::fs;
+    use crypto::sha2::Sha256;
+    use super::{FileNodeStore, NodeStore};
+    use merkle_tree::MerkleTree;
+    use merkle_error::MerkleError;
+    use test_helpers::{ODD_NODE_POLICIES, temp_path};
+
+    fn node(value: u8) -> [u8; 32] {
+        [value; 32]
+    }
+
+    fn check_store<S: NodeStore<[u8; 32]>>(store: &mut S) {
+        assert_eq!(0, store.layers_count());
+        store.put_batch(0, 0, &[node(1), node(2), node(3)]).unwrap();
+        store.put(0, 1, node(4)).unwrap();
+        store.put(1, 0, node(5)).unwrap();
+        assert_eq!(2, store.layers_count());
+        assert_eq!(3, store.layer_len(0));
+        assert_eq!(0, store.layer_len(2));
+        assert_eq!(vec![node(1), node(4), node(3)], store.get_batch(0, 0, 3).unwrap());
+        assert_eq!(node(5), store.get(1, 0).unwrap());
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }), store.get(0, 3));
+        assert_eq!(Ok(Vec::new()), store.get_batch(5, 0, 0));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 0, len: 0 }), store.get(5, 0));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 2, len: 1 }),
+                   store.put(1, 2, node(6)));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 3, len: 2 }),
+                   store.put(3, 0, node(6)));
+    }
+
+    #[test]
+    fn memory_store() {
+        let _ = env_logger::init();
+        let mut store: Vec<Vec<[u8; 32]>> = Vec::new();
+        check_store(&mut store);
+    }
+
+    #[test]
+    fn file_store() {
+        let _ = env_logger::init();
+        let directory = temp_path("file_store");
+        {
+            let mut store = FileNodeStore::create(&directory).unwrap();
+            check_store(&mut store);
+            store.sync().unwrap();
+        }
+        let store: FileNodeStore<[u8; 32]> = FileNodeStore::open(&directory).unwrap();
+        assert_eq!(vec![3, 1], vec![store.layer_len(0), store.layer_len(1)]);
+        assert_eq!(node(4), store.get(0, 1).unwrap());
+        assert_eq!(0, FileNodeStore::<[u8; 32]>::create(&directory).unwrap().layers_count());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn build_tree_in_file_store() {
+        let _ = env_logger::init();
+        let directory = temp_path("build_tree_in_file_store");
+        for policy in ODD_NODE_POLICIES.iter() {
+            for leaves_count in 1..12u8 {
+                let leaves: Vec<[u8; 32]> = (0..leaves_count).map(node).collect();
+                let mut expected: MerkleTree = MerkleTree {
+                    odd_node_policy: *policy,
+                    ..MerkleTree::default()
+                };
+                expected.push_batch(&leaves);
+                expected.build().unwrap();
+
+                let store = FileNodeStore::create(&directory).unwrap();
+                let mut merkle_tree = MerkleTree::with_store(Sha256::new(), store);
+                merkle_tree.odd_node_policy = *policy;
+                merkle_tree.store_batch_size = 2;
+                let (head, tail) = leaves.split_at(leaves.len().div_ceil(2));
+                merkle_tree.append_leaves(head).unwrap();
+                merkle_tree.build_store().unwrap();
+                merkle_tree.append_leaves(tail).unwrap();
+                assert_eq!(Err(MerkleError::NotBuilt), merkle_tree.get_merkle_root());
+                assert_eq!(expected.get_merkle_root(), merkle_tree.build_store());
+
+                let mut reopened = MerkleTree::with_store(Sha256::new(),
+                                                          FileNodeStore::open(&directory).unwrap());
+                reopened.odd_node_policy = *policy;
+                reopened.builded = true;
+                assert_eq!(expected.get_merkle_root(), reopened.get_merkle_root());
+                for leaf_index in 0..leaves.len() {
+                    assert_eq!(expected.proof_by_index(leaf_index),
+                               reopened.proof_by_index(leaf_index));
+                }
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+/// Правило обработки узла, которому не нашлось пары в конце слоя дерева.
+/// Должно быть выбрано до построения дерева.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum OddNodePolicy {
+    /// Одиночный узел хэшируется повторно как "лист". Единственный "лист" дерева так же
+    /// хэшируется повторно для получения корня.
+    #[default]
+    Rehash,
+    /// Одиночный узел объединяется с собственной копией (как в Bitcoin).
+    Duplicate,
+    /// Одиночный узел переносится на следующий слой без изменений (как в RFC 6962).
+    Promote,
+    /// Одиночный узел объединяется с узлом, заполненным нулями.
+    ZeroPad,
+}
@@ -0,0 +1,316 @@
+use hasher::{Hasher, Keccak256};
+use tree_mode::TreeMode;
+use hash_function::hash_leaf;
+use rlp::RlpItem;
+use patricia_trie::{to_nibbles, hex_prefix_decode};
+use merkle_error::MerkleError;
+
+/// Доказательство для ключа Merkle Patricia trie в формате `eth_getProof`: RLP-кодировки узлов на
+/// пути от корня к ключу. Узлы, встроенные в родителей, отдельно не передаются.
+///
+/// Подтверждает значение ключа либо его отсутствие: путь может закончиться пустой ссылкой
+/// ветвления или разойтись с путём "листа" или расширения.
+#[derive(Clone)]
+pub struct PatriciaProof<D: Hasher> {
+    /// Путь в trie в байтах. Для "secure" trie - хэш исходного ключа.
+    pub key: Vec<u8>,
+    /// RLP-кодировки узлов, начиная с корневого.
+    pub nodes: Vec<Vec<u8>>,
+    /// Хэш-функция trie.
+    pub digest: D,
+}
+
+impl_debug_eq!(PatriciaProof { key, nodes });
+
+impl<D: Hasher> PatriciaProof<D> {
+    /// Проходит по узлам доказательства от корня `root` и возвращает значение ключа или `None`,
+    /// если доказано его отсутствие. Узлы после последнего нужного игнорируются.
+    ///
+    /// # Failures
+    /// В случае, если узла не хватает или его хэш не совпадает со ссылкой родителя возвращается
+    /// `MerkleError::InvalidProof`, если узел не удалось разобрать - `MerkleError::Deserialization`.
+    pub fn value(&self, root: &D::Node) -> Result<Option<Vec<u8>>, MerkleError> {
+        let path = to_nibbles(&self.key);
+        let mut rest = path.as_slice();
+        let mut nodes = self.nodes.iter();
+        let mut reference = RlpItem::Bytes(root.as_ref().to_vec());
+        loop {
+            let node = match reference {
+                RlpItem::Bytes(ref hash) if hash.is_empty() => return Ok(None),
+                RlpItem::Bytes(ref hash) => {
+                    let encoded = nodes.next().ok_or(MerkleError::InvalidProof)?;
+                    if hash_leaf(&self.digest, TreeMode::Classic, encoded).as_ref() != hash.as_slice() {
+                        return Err(MerkleError::InvalidProof);
+                    }
+                    RlpItem::decode(encoded)?
+                }
+                RlpItem::List(_) => reference,
+            };
+            let items = match node {
+                RlpItem::Bytes(ref bytes) if bytes.is_empty() => return Ok(None),
+                RlpItem::List(items) => items,
+                RlpItem::Bytes(_) => return Err(invalid_node()),
+            };
+            match items.len() {
+                2 => {
+                    let (node_path, leaf) = hex_prefix_decode(items[0].as_bytes().ok_or_else(invalid_node)?)?;
+                    if leaf {
+                        if rest != node_path.as_slice() {
+                            return Ok(None);
+                        }
+                        return Ok(Some(items[1].as_bytes().ok_or_else(invalid_node)?.to_vec()));
+                    }
+                    if !rest.starts_with(&node_path) {
+                        return Ok(None);
+                    }
+                    rest = &rest[node_path.len()..];
+                    reference = items[1].clone();
+                }
+                17 => {
+                    match rest.split_first() {
+                        Some((&nibble, tail)) => {
+                            rest = tail;
+                            reference = items[nibble as usize].clone();
+                        }
+                        None => {
+                            let value = items[16].as_bytes().ok_or_else(invalid_node)?;
+                            return Ok(if value.is_empty() { None } else { Some(value.to_vec()) });
+                        }
+                    }
+                }
+                _ => return Err(invalid_node()),
+            }
+        }
+    }
+
+    /// Проверяет, что в trie с корнем `root` по ключу записано значение `value`.
+    pub fn verify_value(&self, root: &D::Node, value: &[u8]) -> bool {
+        self.value(root) == Ok(Some(value.to_vec()))
+    }
+
+    /// Проверяет, что ключа нет в trie с корнем `root`.
+    pub fn verify_absence(&self, root: &D::Node) -> bool {
+        self.value(root) == Ok(None)
+    }
+}
+
+impl PatriciaProof<Keccak256> {
+    /// Доказательство для аккаунта из поля `accountProof` ответа `eth_getProof`. Путём в trie
+    /// состояния служит `keccak256(address)`.
+    pub fn account(address: &[u8; 20], nodes: Vec<Vec<u8>>) -> PatriciaProof<Keccak256> {
+        PatriciaProof::secure(address, nodes)
+    }
+
+    /// Доказательство для ячейки хранилища из поля `storageProof[].proof` ответа `eth_getProof`.
+    /// Путём в trie хранилища служит `keccak256(slot)`, где `slot` дополнен нулями слева до 32 байт.
+    pub fn storage(slot: &[u8; 32], nodes: Vec<Vec<u8>>) -> PatriciaProof<Keccak256> {
+        PatriciaProof::secure(slot, nodes)
+    }
+
+    /// Проверяет доказательство относительно `stateRoot` блока и возвращает аккаунт или `None`,
+    /// если аккаунта нет.
+    ///
+    /// # Failures
+    /// Смотри `PatriciaProof::value`; если значение не является аккаунтом, возвращается
+    /// `MerkleError::Deserialization`.
+    pub fn verify_account(&self, state_root: &[u8; 32]) -> Result<Option<EthAccount>, MerkleError> {
+        match self.value(state_root)? {
+            Some(value) => Ok(Some(EthAccount::from_rlp(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Проверяет доказательство относительно `storageHash` аккаунта и возвращает значение ячейки
+    /// в big-endian без ведущих нулей. Отсутствующая ячейка равна нулю - пустому значению.
+    ///
+    /// # Failures
+    /// Смотри `PatriciaProof::value`; если значение не является строкой байт в RLP, возвращается
+    /// `MerkleError::Deserialization`.
+    pub fn verify_storage(&self, storage_root: &[u8; 32]) -> Result<Vec<u8>, MerkleError> {
+        match self.value(storage_root)? {
+            Some(value) => {
+                match RlpItem::decode(&value)? {
+                    RlpItem::Bytes(bytes) => Ok(bytes),
+                    RlpItem::List(_) => Err(invalid_node()),
+                }
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn secure(key: &[u8], nodes: Vec<Vec<u8>>) -> PatriciaProof<Keccak256> {
+        let digest = Keccak256::new();
+        PatriciaProof {
+            key: hash_leaf(&digest, TreeMode::Classic, key).to_vec(),
+            nodes,
+            digest,
+        }
+    }
+}
+
+/// Аккаунт Ethereum - значение в trie состояния.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EthAccount {
+    /// Количество отправленных транзакций.
+    pub nonce: u64,
+    /// Баланс в wei, big-endian без ведущих нулей.
+    pub balance: Vec<u8>,
+    /// Корень trie хранилища (`storageHash`).
+    pub storage_root: [u8; 32],
+    /// Хэш кода контракта.
+    pub code_hash: [u8; 32],
+}
+
+impl EthAccount {
+    /// Кодирует аккаунт в RLP: `[nonce, balance, storageRoot, codeHash]`.
+    pub fn to_rlp(&self) -> Vec<u8> {
+        RlpItem::List(vec![RlpItem::uint(self.nonce),
+                           RlpItem::Bytes(self.balance.clone()),
+                           RlpItem::Bytes(self.storage_root.to_vec()),
+                           RlpItem::Bytes(self.code_hash.to_vec())])
+            .encode()
+    }
+
+    /// Разбирает аккаунт из RLP.
+    ///
+    /// # Failures
+    /// В случае, если данные не являются списком из четырёх полей аккаунта возвращается
+    /// `MerkleError::Deserialization`.
+    pub fn from_rlp(data: &[u8]) -> Result<EthAccount, MerkleError> {
+        let item = RlpItem::decode(data)?;
+        let fields = match item.as_list() {
+            Some(fields) if fields.len() == 4 => fields,
+            _ => return Err(invalid_account()),
+        };
+        let balance = fields[1].as_bytes().ok_or_else(invalid_account)?;
+        let hash = |field: &RlpItem| {
+            let bytes = field.as_bytes().filter(|bytes| bytes.len() == 32).ok_or_else(invalid_account)?;
+            let mut hash = [0; 32];
+            hash.copy_from_slice(bytes);
+            Ok(hash)
+        };
+        Ok(EthAccount {
+            nonce: fields[0].to_uint()?,
+            balance: balance.to_vec(),
+            storage_root: hash(&fields[2])?,
+            code_hash: hash(&fields[3])?,
+        })
+    }
+}
+
+fn invalid_node() -> MerkleError {
+    MerkleError::Deserialization("Invalid trie node".to_string())
+}
+
+fn invalid_account() -> MerkleError {
+    MerkleError::Deserialization("Invalid account".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use super::{PatriciaProof, EthAccount};
+    use patricia_trie::PatriciaTrie;
+    use rlp::RlpItem;
+    use merkle_error::MerkleError;
+
+    fn puppy_trie() -> PatriciaTrie {
+        let mut trie = PatriciaTrie::default();
+        for &(key, value) in &[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")] {
+            trie.insert(key.as_bytes(), value.as_bytes());
+        }
+        trie
+    }
+
+    #[test]
+    fn inclusion_and_absence() {
+        let _ = env_logger::init();
+        let trie = puppy_trie();
+        let root = trie.root();
+        for &(key, value) in &[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")] {
+            let proof = trie.proof(key.as_bytes());
+            assert!(proof.verify_value(&root, value.as_bytes()));
+            assert!(!proof.verify_value(&root, b"cat"));
+            assert!(!proof.verify_absence(&root));
+        }
+        for key in &["d", "dogs", "cat", "horses", "hors", ""] {
+            assert!(trie.proof(key.as_bytes()).verify_absence(&root));
+        }
+        let empty = PatriciaTrie::default();
+        assert!(empty.proof(b"dog").verify_absence(&empty.root()));
+    }
+
+    #[test]
+    fn reject_tampered_proof() {
+        let _ = env_logger::init();
+        let trie = puppy_trie();
+        let root = trie.root();
+        let proof = trie.proof(b"horse");
+        assert!(proof.nodes.len() > 1);
+
+        let mut tampered = proof.clone();
+        let last = tampered.nodes.len() - 1;
+        tampered.nodes[last][3] ^= 1;
+        assert_eq!(Err(MerkleError::InvalidProof), tampered.value(&root));
+
+        let mut truncated = proof.clone();
+        truncated.nodes.pop();
+        assert_eq!(Err(MerkleError::InvalidProof), truncated.value(&root));
+
+        let mut other_key = proof.clone();
+        other_key.key = b"dog".to_vec();
+        assert!(!other_key.verify_value(&root, b"stallion"));
+        assert_eq!(Err(MerkleError::InvalidProof), proof.value(&[0; 32]));
+    }
+
+    #[test]
+    fn account_and_storage_proofs() {
+        let _ = env_logger::init();
+        let mut storage = PatriciaTrie::default();
+        storage.secure = true;
+        let mut slots = Vec::new();
+        for slot_index in 0..20u64 {
+            let mut slot = [0; 32];
+            slot[24..].copy_from_slice(&slot_index.to_be_bytes());
+            storage.insert(&slot, &RlpItem::uint(slot_index * 1000 + 1).encode());
+            slots.push(slot);
+        }
+        let storage_root = storage.root();
+
+        let mut state = PatriciaTrie::default();
+        state.secure = true;
+        let mut accounts = Vec::new();
+        for account_index in 0..50u8 {
+            let address = [account_index; 20];
+            let account = EthAccount {
+                nonce: account_index as u64,
+                balance: vec![account_index + 1, 0, 0],
+                storage_root,
+                code_hash: [account_index; 32],
+            };
+            state.insert(&address, &account.to_rlp());
+            accounts.push((address, account));
+        }
+        let state_root = state.root();
+
+        for (address, account) in &accounts {
+            let proof = PatriciaProof::account(address, state.proof(address).nodes);
+            assert_eq!(state.proof(address), proof);
+            assert_eq!(Ok(Some(account.clone())), proof.verify_account(&state_root));
+        }
+        assert_eq!(Ok(None),
+                   PatriciaProof::account(&[0xff; 20], state.proof(&[0xff; 20]).nodes).verify_account(&state_root));
+
+        for (slot_index, slot) in slots.iter().enumerate() {
+            let proof = PatriciaProof::storage(slot, storage.proof(slot).nodes);
+            assert_eq!(Ok(RlpItem::uint(slot_index as u64 * 1000 + 1).as_bytes().unwrap().to_vec()),
+                       proof.verify_storage(&storage_root));
+        }
+        let missing = [0xff; 32];
+        assert_eq!(Ok(Vec::new()),
+                   PatriciaProof::storage(&missing, storage.proof(&missing).nodes).verify_storage(&storage_root));
+        assert!(matches!(PatriciaProof::storage(&slots[1], storage.proof(&slots[1]).nodes).verify_account(&storage_root),
+                         Err(MerkleError::Deserialization(_))));
+    }
+}
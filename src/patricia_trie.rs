@@ -0,0 +1,461 @@
+use std::fmt;
+use std::mem;
+use hasher::{Hasher, Keccak256};
+use tree_mode::TreeMode;
+use hash_function::hash_leaf;
+use rlp::RlpItem;
+use patricia_proof::PatriciaProof;
+use merkle_error::MerkleError;
+
+/// Узел trie. Пути хранятся в полубайтах (nibbles) ключа.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum TrieNode {
+    Empty,
+    Leaf { path: Vec<u8>, value: Vec<u8> },
+    Extension { path: Vec<u8>, child: Box<TrieNode> },
+    Branch { children: Vec<TrieNode>, value: Option<Vec<u8>> },
+}
+
+/// Merkle Patricia trie в формате Ethereum.
+///
+/// Ключи разбиваются на полубайты; узел-ветвление (branch) имеет 16 потомков и значение для
+/// ключа, заканчивающегося в нём, узел-расширение (extension) хранит общую часть путей, а "лист" -
+/// остаток пути и значение. Узлы кодируются в RLP; узел, кодировка которого короче 32 байт,
+/// встраивается в родителя, остальные заменяются хэшем кодировки. Корень - хэш кодировки
+/// корневого узла, поэтому с `Keccak256` он совпадает с `stateRoot`, `transactionsRoot` и
+/// `receiptsRoot` Ethereum для тех же ключей и значений.
+///
+/// Хэши узлов не кэшируются: `root` и `proof` перекодируют trie целиком.
+pub struct PatriciaTrie<D: Hasher = Keccak256> {
+    /// Экземпляр хэш-функции, который клонируется при хэшировании каждого узла.
+    pub digest: D,
+    /// Хэшировать ли ключи перед вставкой, как в "secure" trie состояния и хранилищ Ethereum,
+    /// где путём служит `keccak256(address)` или `keccak256(slot)`. Меняется только до вставки
+    /// ключей.
+    pub secure: bool,
+    root: TrieNode,
+}
+
+impl Default for PatriciaTrie<Keccak256> {
+    fn default() -> PatriciaTrie<Keccak256> {
+        PatriciaTrie::with_digest(Keccak256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for PatriciaTrie<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PatriciaTrie")
+            .field("secure", &self.secure)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl<D: Hasher> PatriciaTrie<D> {
+    /// Создаёт пустой trie, использующий переданную хэш-функцию.
+    pub fn with_digest(digest: D) -> PatriciaTrie<D> {
+        PatriciaTrie {
+            digest,
+            secure: false,
+            root: TrieNode::Empty,
+        }
+    }
+
+    /// Проверяет, есть ли в trie ключи.
+    pub fn is_empty(&self) -> bool {
+        self.root == TrieNode::Empty
+    }
+
+    /// Записывает значение по ключу. Как и в Ethereum, пустое значение удаляет ключ.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        if value.is_empty() {
+            self.remove(key);
+            return;
+        }
+        let path = to_nibbles(&self.path_key(key));
+        let root = mem::replace(&mut self.root, TrieNode::Empty);
+        self.root = insert_node(root, &path, value.to_vec());
+    }
+
+    /// Удаляет ключ. Возвращает `false`, если ключа не было.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let path = to_nibbles(&self.path_key(key));
+        let root = mem::replace(&mut self.root, TrieNode::Empty);
+        let (root, removed) = remove_node(root, &path);
+        self.root = root;
+        removed
+    }
+
+    /// Возвращает значение, записанное по ключу.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let path = to_nibbles(&self.path_key(key));
+        let mut node = &self.root;
+        let mut rest = path.as_slice();
+        loop {
+            match *node {
+                TrieNode::Empty => return None,
+                TrieNode::Leaf { ref path, ref value } => {
+                    return if rest == path.as_slice() { Some(value) } else { None };
+                }
+                TrieNode::Extension { ref path, ref child } => {
+                    if !rest.starts_with(path) {
+                        return None;
+                    }
+                    rest = &rest[path.len()..];
+                    node = child;
+                }
+                TrieNode::Branch { ref children, ref value } => {
+                    match rest.split_first() {
+                        Some((&nibble, tail)) => {
+                            rest = tail;
+                            node = &children[nibble as usize];
+                        }
+                        None => return value.as_ref().map(|value| value.as_slice()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Возвращает корень trie - хэш RLP-кодировки корневого узла. Корень пустого trie - хэш
+    /// пустой строки в RLP.
+    pub fn root(&self) -> D::Node {
+        hash_leaf(&self.digest, TreeMode::Classic, &encode_node(&self.root, &self.digest).encode())
+    }
+
+    /// Возвращает доказательство в формате `eth_getProof`: RLP-кодировки узлов на пути к ключу,
+    /// начиная с корня, кроме встроенных в родителей. Доказательство подтверждает как значение
+    /// ключа, так и его отсутствие.
+    pub fn proof(&self, key: &[u8]) -> PatriciaProof<D> {
+        let key = self.path_key(key);
+        let path = to_nibbles(&key);
+        let mut nodes = vec![encode_node(&self.root, &self.digest).encode()];
+        let mut node = &self.root;
+        let mut rest = path.as_slice();
+        loop {
+            let child = match *node {
+                TrieNode::Extension { ref path, ref child } if rest.starts_with(path) => {
+                    rest = &rest[path.len()..];
+                    child
+                }
+                TrieNode::Branch { ref children, .. } if !rest.is_empty() => {
+                    let child = &children[rest[0] as usize];
+                    rest = &rest[1..];
+                    child
+                }
+                _ => break,
+            };
+            let encoded = encode_node(child, &self.digest).encode();
+            if encoded.len() >= 32 {
+                nodes.push(encoded);
+            }
+            node = child;
+        }
+        PatriciaProof {
+            key,
+            nodes,
+            digest: self.digest.clone(),
+        }
+    }
+
+    /// Ключ, по которому строится путь в trie.
+    fn path_key(&self, key: &[u8]) -> Vec<u8> {
+        if self.secure {
+            hash_leaf(&self.digest, TreeMode::Classic, key).as_ref().to_vec()
+        } else {
+            key.to_vec()
+        }
+    }
+}
+
+/// Разбивает байты на полубайты, начиная со старшего.
+pub fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|&byte| vec![byte >> 4, byte & 0x0f]).collect()
+}
+
+/// Кодирует путь в полубайтах в "hex-prefix" формат Ethereum: старший полубайт первого байта
+/// содержит признак "листа" и чётности длины пути, при нечётной длине младший полубайт - первый
+/// полубайт пути.
+pub fn hex_prefix_encode(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 } + (path.len() % 2) as u8;
+    let (first, rest) = if path.len() % 2 == 1 {
+        (flag << 4 | path[0], &path[1..])
+    } else {
+        (flag << 4, path)
+    };
+    let mut encoded = vec![first];
+    encoded.extend(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+    encoded
+}
+
+/// Разбирает путь в "hex-prefix" формате. Возвращает полубайты пути и признак "листа".
+///
+/// # Failures
+/// В случае, если путь пуст или признак некорректен возвращается `MerkleError::Deserialization`.
+pub fn hex_prefix_decode(encoded: &[u8]) -> Result<(Vec<u8>, bool), MerkleError> {
+    let first = *encoded.first()
+        .ok_or_else(|| MerkleError::Deserialization("Empty trie node path".to_string()))?;
+    let flag = first >> 4;
+    if flag > 3 || (flag & 1 == 0 && first & 0x0f != 0) {
+        return Err(MerkleError::Deserialization(format!("Invalid trie node path flag {}", flag)));
+    }
+    let mut path = if flag & 1 == 1 { vec![first & 0x0f] } else { Vec::new() };
+    path.extend(to_nibbles(&encoded[1..]));
+    Ok((path, flag & 2 == 2))
+}
+
+/// Длина общего префикса двух путей.
+fn common_prefix(left: &[u8], right: &[u8]) -> usize {
+    left.iter().zip(right).take_while(|&(left, right)| left == right).count()
+}
+
+fn empty_children() -> Vec<TrieNode> {
+    vec![TrieNode::Empty; 16]
+}
+
+/// Вставляет значение в поддерево с корнем `node` по оставшемуся пути `path`.
+fn insert_node(node: TrieNode, path: &[u8], value: Vec<u8>) -> TrieNode {
+    match node {
+        TrieNode::Empty => {
+            TrieNode::Leaf {
+                path: path.to_vec(),
+                value,
+            }
+        }
+        TrieNode::Leaf { path: leaf_path, value: leaf_value } => {
+            if leaf_path == path {
+                return TrieNode::Leaf { path: leaf_path, value };
+            }
+            let common = common_prefix(&leaf_path, path);
+            let branch = TrieNode::Branch {
+                children: empty_children(),
+                value: None,
+            };
+            let branch = insert_node(branch, &leaf_path[common..], leaf_value);
+            let branch = insert_node(branch, &path[common..], value);
+            with_extension(&path[..common], branch)
+        }
+        TrieNode::Extension { path: extension_path, child } => {
+            let common = common_prefix(&extension_path, path);
+            if common == extension_path.len() {
+                return TrieNode::Extension {
+                    child: Box::new(insert_node(*child, &path[common..], value)),
+                    path: extension_path,
+                };
+            }
+            let mut children = empty_children();
+            children[extension_path[common] as usize] = with_extension(&extension_path[common + 1..], *child);
+            let branch = TrieNode::Branch {
+                children,
+                value: None,
+            };
+            with_extension(&path[..common], insert_node(branch, &path[common..], value))
+        }
+        TrieNode::Branch { mut children, value: branch_value } => {
+            match path.split_first() {
+                Some((&nibble, rest)) => {
+                    let child = mem::replace(&mut children[nibble as usize], TrieNode::Empty);
+                    children[nibble as usize] = insert_node(child, rest, value);
+                    TrieNode::Branch {
+                        children,
+                        value: branch_value,
+                    }
+                }
+                None => {
+                    TrieNode::Branch {
+                        children,
+                        value: Some(value),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Удаляет ключ из поддерева с корнем `node` по оставшемуся пути `path`, сохраняя каноническую
+/// форму: ветвления с одним потомком сворачиваются, расширения сливаются с потомками.
+fn remove_node(node: TrieNode, path: &[u8]) -> (TrieNode, bool) {
+    match node {
+        TrieNode::Empty => (TrieNode::Empty, false),
+        TrieNode::Leaf { path: leaf_path, value } => {
+            if leaf_path == path {
+                (TrieNode::Empty, true)
+            } else {
+                (TrieNode::Leaf { path: leaf_path, value }, false)
+            }
+        }
+        TrieNode::Extension { path: extension_path, child } => {
+            if !path.starts_with(&extension_path) {
+                return (TrieNode::Extension { path: extension_path, child }, false);
+            }
+            let (child, removed) = remove_node(*child, &path[extension_path.len()..]);
+            (with_extension(&extension_path, child), removed)
+        }
+        TrieNode::Branch { mut children, value } => {
+            let removed = match path.split_first() {
+                Some((&nibble, rest)) => {
+                    let child = mem::replace(&mut children[nibble as usize], TrieNode::Empty);
+                    let (child, removed) = remove_node(child, rest);
+                    children[nibble as usize] = child;
+                    removed
+                }
+                None => value.is_some(),
+            };
+            if !removed {
+                return (TrieNode::Branch { children, value }, false);
+            }
+            let value = if path.is_empty() { None } else { value };
+            (collapse_branch(children, value), true)
+        }
+    }
+}
+
+/// Сворачивает ветвление, у которого после удаления осталось меньше двух потомков и значений.
+fn collapse_branch(mut children: Vec<TrieNode>, value: Option<Vec<u8>>) -> TrieNode {
+    let mut occupied = (0..16).filter(|&nibble| children[nibble] != TrieNode::Empty);
+    match (occupied.next(), occupied.next(), value) {
+        (None, _, Some(value)) => TrieNode::Leaf { path: Vec::new(), value },
+        (Some(nibble), None, None) => {
+            let child = mem::replace(&mut children[nibble], TrieNode::Empty);
+            with_extension(&[nibble as u8], child)
+        }
+        (_, _, value) => TrieNode::Branch { children, value },
+    }
+}
+
+/// Добавляет путь `prefix` перед узлом: сливает его с путём "листа" или расширения, а перед
+/// ветвлением создаёт расширение.
+fn with_extension(prefix: &[u8], node: TrieNode) -> TrieNode {
+    if prefix.is_empty() {
+        return node;
+    }
+    match node {
+        TrieNode::Empty => TrieNode::Empty,
+        TrieNode::Leaf { path, value } => TrieNode::Leaf { path: [prefix, &path].concat(), value },
+        TrieNode::Extension { path, child } => {
+            TrieNode::Extension {
+                path: [prefix, &path].concat(),
+                child,
+            }
+        }
+        branch => {
+            TrieNode::Extension {
+                path: prefix.to_vec(),
+                child: Box::new(branch),
+            }
+        }
+    }
+}
+
+/// Кодирует узел в RLP.
+fn encode_node<D: Hasher>(node: &TrieNode, digest: &D) -> RlpItem {
+    match *node {
+        TrieNode::Empty => RlpItem::Bytes(Vec::new()),
+        TrieNode::Leaf { ref path, ref value } => {
+            RlpItem::List(vec![RlpItem::Bytes(hex_prefix_encode(path, true)),
+                               RlpItem::Bytes(value.clone())])
+        }
+        TrieNode::Extension { ref path, ref child } => {
+            RlpItem::List(vec![RlpItem::Bytes(hex_prefix_encode(path, false)),
+                               node_reference(child, digest)])
+        }
+        TrieNode::Branch { ref children, ref value } => {
+            let mut items: Vec<RlpItem> =
+                children.iter().map(|child| node_reference(child, digest)).collect();
+            items.push(RlpItem::Bytes(value.clone().unwrap_or_default()));
+            RlpItem::List(items)
+        }
+    }
+}
+
+/// Ссылка на потомка внутри родителя: сам узел, если его кодировка короче 32 байт, иначе хэш
+/// кодировки.
+fn node_reference<D: Hasher>(node: &TrieNode, digest: &D) -> RlpItem {
+    let item = encode_node(node, digest);
+    if *node == TrieNode::Empty {
+        return item;
+    }
+    let encoded = item.encode();
+    if encoded.len() < 32 {
+        item
+    } else {
+        RlpItem::Bytes(hash_leaf(digest, TreeMode::Classic, &encoded).as_ref().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use super::{PatriciaTrie, hex_prefix_encode, hex_prefix_decode};
+    use hasher::Keccak256;
+    use tree_mode::TreeMode;
+    use hash_function::hash_leaf;
+    use test_helpers::from_hex;
+
+    fn build_trie(pairs: &[(&str, &str)]) -> PatriciaTrie {
+        let mut trie = PatriciaTrie::default();
+        for &(key, value) in pairs {
+            trie.insert(key.as_bytes(), value.as_bytes());
+        }
+        trie
+    }
+
+    #[test]
+    fn keccak_and_empty_root() {
+        let _ = env_logger::init();
+        assert_eq!(from_hex("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"),
+                   hash_leaf(&Keccak256::new(), TreeMode::Classic, b"").to_vec());
+        assert_eq!(from_hex("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+                   PatriciaTrie::default().root().to_vec());
+        for &(path, leaf) in &[(&[][..], true), (&[1, 2, 3][..], false), (&[0xf, 1, 0xc, 0xb][..], true)] {
+            assert_eq!(Ok((path.to_vec(), leaf)), hex_prefix_decode(&hex_prefix_encode(path, leaf)));
+        }
+        assert_eq!(vec![0x20, 0x0f, 0x1c, 0xb8], hex_prefix_encode(&[0, 0xf, 1, 0xc, 0xb, 8], true));
+    }
+
+    #[test]
+    fn ethereum_trie_roots() {
+        let _ = env_logger::init();
+        let puppy = [("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")];
+        let root = from_hex("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
+        assert_eq!(root, build_trie(&puppy).root().to_vec());
+        let mut reversed = puppy;
+        reversed.reverse();
+        assert_eq!(root, build_trie(&reversed).root().to_vec());
+
+        let dogs = [("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")];
+        assert_eq!(from_hex("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"),
+                   build_trie(&dogs).root().to_vec());
+
+        let single = [("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")];
+        assert_eq!(from_hex("d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab"),
+                   build_trie(&single).root().to_vec());
+    }
+
+    #[test]
+    fn get_update_remove() {
+        let _ = env_logger::init();
+        let mut trie = build_trie(&[("do", "verb"), ("dog", "puppy"), ("doge", "coin")]);
+        let root = trie.root();
+        assert_eq!(Some(&b"puppy"[..]), trie.get(b"dog"));
+        assert_eq!(None, trie.get(b"d"));
+        assert_eq!(None, trie.get(b"dogs"));
+
+        trie.insert(b"horse", b"stallion");
+        trie.insert(b"dog", b"hound");
+        assert_eq!(Some(&b"hound"[..]), trie.get(b"dog"));
+        trie.insert(b"dog", b"puppy");
+        assert!(trie.remove(b"horse"));
+        assert!(!trie.remove(b"horse"));
+        assert_eq!(root, trie.root());
+
+        trie.insert(b"do", b"");
+        assert_eq!(None, trie.get(b"do"));
+        assert_eq!(build_trie(&[("dog", "puppy"), ("doge", "coin")]).root(), trie.root());
+        assert!(trie.remove(b"dog"));
+        assert!(trie.remove(b"doge"));
+        assert!(trie.is_empty());
+        assert_eq!(PatriciaTrie::default().root(), trie.root());
+    }
+}
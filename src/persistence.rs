@@ -0,0 +1,329 @@
+use std::io::{Read, Write};
+use bincode;
+use hash_function::{hash_node, hash_lonely, hash_single};
+use hasher::{Hasher, Node};
+use merkle_tree::{MerkleTree, DEFAULT_STORE_BATCH_SIZE};
+use serialization_format::SerializationFormat;
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
+use removal_policy::RemovalPolicy;
+use merkle_error::MerkleError;
+
+/// Сигнатура, с которой начинается сохранённое дерево.
+pub const MAGIC: &[u8; 4] = b"MRKL";
+
+/// Версия формата сохранённого дерева.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Максимальный размер заголовка. Защищает от выделения памяти под заведомо испорченный заголовок.
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+/// Заголовок сохранённого дерева: настройки дерева и размеры его слоёв.
+/// Записывается в формате bincode после сигнатуры, версии и длины заголовка. Следом за
+/// заголовком идут байты узлов всех слоёв подряд, начиная со слоя "листьев".
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TreeHeader {
+    /// Размер узла в байтах. Должен совпадать с размером выхода хэш-функции при загрузке.
+    pub node_size: u32,
+    /// Формат сериализации транзакций.
+    pub format: SerializationFormat,
+    /// Режим хэширования узлов.
+    pub mode: TreeMode,
+    /// Правило обработки одиночных узлов.
+    pub odd_node_policy: OddNodePolicy,
+    /// Правило удаления "листьев".
+    pub removal_policy: RemovalPolicy,
+    /// Было ли дерево построено.
+    pub builded: bool,
+    /// Флаг параллелизации.
+    pub parallel: bool,
+    /// Минимальный размер слоя для многопоточной обработки.
+    pub min_parallel_layer_size: u64,
+    /// Количество узлов в каждом слое.
+    pub layer_lengths: Vec<u64>,
+}
+
+impl TreeHeader {
+    /// Записывает сигнатуру, версию и заголовок.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), MerkleError> {
+        let header = bincode::serialize(self, bincode::Infinite)
+            .map_err(|error| MerkleError::Serialization(error.to_string()))?;
+        let mut prefix = Vec::with_capacity(MAGIC.len() + 6);
+        prefix.extend_from_slice(MAGIC);
+        prefix.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        prefix.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        write_all(writer, &prefix)?;
+        write_all(writer, &header)
+    }
+
+    /// Читает и проверяет сигнатуру, версию и заголовок.
+    ///
+    /// # Failures
+    /// Возвращает `MerkleError::Deserialization`, если сигнатура, версия или заголовок неверны, и
+    /// `MerkleError::Io` при ошибке чтения.
+    pub fn read<R: Read>(reader: &mut R) -> Result<TreeHeader, MerkleError> {
+        let mut prefix = [0u8; 10];
+        read_exact(reader, &mut prefix)?;
+        if &prefix[..4] != MAGIC {
+            return Err(MerkleError::Deserialization("Bad magic".to_string()));
+        }
+        let version = u16::from_le_bytes([prefix[4], prefix[5]]);
+        if version != FORMAT_VERSION {
+            return Err(MerkleError::Deserialization(format!("Unsupported version {}", version)));
+        }
+        let header_len = u32::from_le_bytes([prefix[6], prefix[7], prefix[8], prefix[9]]);
+        if header_len > MAX_HEADER_LEN {
+            return Err(MerkleError::Deserialization(format!("Header too large: {} bytes",
+                                                            header_len)));
+        }
+        let mut header = vec![0u8; header_len as usize];
+        read_exact(reader, &mut header)?;
+        bincode::deserialize(&header)
+            .map_err(|error| MerkleError::Deserialization(error.to_string()))
+    }
+
+    /// Создаёт дерево со слоями `layers` и настройками из заголовка. Размеры слоёв не
+    /// проверяются, настройки, не входящие в заголовок, берутся по умолчанию.
+    pub fn tree<D: Hasher, S>(&self, digest: D, layers: S) -> MerkleTree<D, S> {
+        MerkleTree {
+            layers,
+            format: self.format,
+            parallel: self.parallel,
+            min_parallel_layer_size: self.min_parallel_layer_size as usize,
+            store_batch_size: DEFAULT_STORE_BATCH_SIZE,
+            thread_pool: None,
+            builded: self.builded,
+            digest,
+            mode: self.mode,
+            odd_node_policy: self.odd_node_policy,
+            removal_policy: self.removal_policy,
+        }
+    }
+}
+
+impl<D> MerkleTree<D>
+    where D: Hasher
+{
+    /// Сохраняет дерево в компактном бинарном формате: сигнатура, версия формата, заголовок с
+    /// настройками дерева и размерами слоёв, затем узлы всех слоёв подряд.
+    /// Пул потоков не сохраняется. Для записи в файл `writer` стоит обернуть в `BufWriter`.
+    /// Сохранённое в файл дерево можно открыть без загрузки слоёв методом `MerkleTree::open_mmap`.
+    ///
+    /// # Failures
+    /// При ошибке записи возвращает `MerkleError::Io`.
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<(), MerkleError> {
+        let header = TreeHeader {
+            node_size: D::Node::zeroed().as_ref().len() as u32,
+            format: self.format,
+            mode: self.mode,
+            odd_node_policy: self.odd_node_policy,
+            removal_policy: self.removal_policy,
+            builded: self.builded,
+            parallel: self.parallel,
+            min_parallel_layer_size: self.min_parallel_layer_size as u64,
+            layer_lengths: self.layers.iter().map(|layer| layer.len() as u64).collect(),
+        };
+        header.write(writer)?;
+        for node in self.layers.iter().flat_map(|layer| layer.iter()) {
+            write_all(writer, node.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Загружает дерево, сохранённое методом `save`, без пересчёта хэшей.
+    /// Хэш-функция не сохраняется вместе с деревом и передаётся в `digest`. Если установлен флаг
+    /// `verify`, все слои построенного дерева пересчитываются из нижележащих и сравниваются с
+    /// сохранёнными. Для чтения из файла `reader` стоит обернуть в `BufReader`.
+    ///
+    /// # Failures
+    /// Возвращает `MerkleError::Deserialization`, если данные не являются сохранённым деревом или
+    /// размер узла не совпадает с размером выхода `digest`, `MerkleError::Io` при ошибке чтения
+    /// (в том числе при обрыве данных) и `MerkleError::Corrupted`, если при проверке узел не
+    /// совпал с вычисленным из его потомков.
+    pub fn load<R: Read>(reader: &mut R, digest: D, verify: bool) -> Result<MerkleTree<D>, MerkleError> {
+        let header = TreeHeader::read(reader)?;
+        let node_size = D::Node::zeroed().as_ref().len();
+        if header.node_size as usize != node_size {
+            return Err(MerkleError::Deserialization(format!("Node size {} does not match digest \
+                                                             output size {}",
+                                                            header.node_size,
+                                                            node_size)));
+        }
+        if header.layer_lengths.is_empty() {
+            return Err(MerkleError::Deserialization("No layers".to_string()));
+        }
+        let mut layers = Vec::with_capacity(header.layer_lengths.len());
+        for &layer_len in &header.layer_lengths {
+            let mut layer = Vec::new();
+            for _ in 0..layer_len {
+                let mut node = D::Node::zeroed();
+                read_exact(reader, node.as_mut())?;
+                layer.push(node);
+            }
+            layers.push(layer);
+        }
+        let merkle_tree = header.tree(digest, layers);
+        if verify && merkle_tree.builded {
+            merkle_tree.verify_layers()?;
+        }
+        Ok(merkle_tree)
+    }
+
+    /// Пересчитывает каждый слой построенного дерева из нижележащего и сравнивает с сохранённым.
+    /// Слои выше корня должны быть пусты.
+    fn verify_layers(&self) -> Result<(), MerkleError> {
+        if self.layers[0].is_empty() {
+            return Err(MerkleError::EmptyTree);
+        }
+        let mut layer_index = 0;
+        while self.layers[layer_index].len() > 1 || layer_index == 0 {
+            let children = &self.layers[layer_index];
+            let expected: Vec<D::Node> = if children.len() == 1 {
+                vec![hash_single(&self.digest, self.mode, self.odd_node_policy, &children[0])]
+            } else {
+                children.chunks(2)
+                    .map(|pair| if pair.len() == 2 {
+                        hash_node(&self.digest, self.mode, &pair[0], &pair[1])
+                    } else {
+                        hash_lonely(&self.digest, self.mode, self.odd_node_policy, &pair[0])
+                    })
+                    .collect()
+            };
+            let stored = match self.layers.get(layer_index + 1) {
+                Some(stored) => stored.as_slice(),
+                None => &[],
+            };
+            if let Some(index) = (0..expected.len()).find(|&index| stored.get(index) != Some(&expected[index])) {
+                return Err(MerkleError::Corrupted { layer: layer_index + 1, index });
+            }
+            if stored.len() != expected.len() {
+                return Err(MerkleError::Corrupted { layer: layer_index + 1, index: expected.len() });
+            }
+            layer_index += 1;
+        }
+        match self.layers[layer_index + 1..].iter().position(|layer| !layer.is_empty()) {
+            Some(offset) => Err(MerkleError::Corrupted { layer: layer_index + 1 + offset, index: 0 }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Записывает данные, преобразуя ошибку ввода-вывода в `MerkleError::Io`.
+fn write_all<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), MerkleError> {
+    writer.write_all(data).map_err(|error| MerkleError::Io(error.to_string()))
+}
+
+/// Читает данные, преобразуя ошибку ввода-вывода в `MerkleError::Io`.
+fn read_exact<R: Read>(reader: &mut R, data: &mut [u8]) -> Result<(), MerkleError> {
+    reader.read_exact(data).map_err(|error| MerkleError::Io(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use crypto::sha2::{Sha256, Sha512};
+    use hasher::Blake2b256;
+    use merkle_tree::MerkleTree;
+    use serialization_format::SerializationFormat;
+    use removal_policy::RemovalPolicy;
+    use merkle_error::MerkleError;
+    use test_helpers::ODD_NODE_POLICIES;
+
+    #[test]
+    fn save_and_load() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for leaves_count in 1..12usize {
+                let mut merkle_tree: MerkleTree = MerkleTree {
+                    format: SerializationFormat::Bincode,
+                    odd_node_policy: *policy,
+                    removal_policy: RemovalPolicy::Shift,
+                    min_parallel_layer_size: 16,
+                    ..MerkleTree::default()
+                };
+                for leaf in 0..leaves_count {
+                    merkle_tree.push(&leaf).unwrap();
+                }
+                merkle_tree.build().unwrap();
+                let mut stored = Vec::new();
+                merkle_tree.save(&mut stored).unwrap();
+                let mut loaded = MerkleTree::load(&mut stored.as_slice(), Sha256::new(), true)
+                    .unwrap();
+                assert_eq!(merkle_tree, loaded);
+                assert_eq!(merkle_tree.get_merkle_root(), loaded.get_merkle_root());
+                loaded.push(&leaves_count).unwrap();
+                merkle_tree.push(&leaves_count).unwrap();
+                assert_eq!(merkle_tree.get_merkle_root(), loaded.get_merkle_root());
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load_with_digest() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from_with_digest(&["a", "b", "c"],
+                                                           SerializationFormat::Json,
+                                                           Sha512::new())
+            .unwrap();
+        merkle_tree.build().unwrap();
+        let mut stored = Vec::new();
+        merkle_tree.save(&mut stored).unwrap();
+        assert_eq!(merkle_tree,
+                   MerkleTree::load(&mut stored.as_slice(), Sha512::new(), true).unwrap());
+        assert!(matches!(MerkleTree::load(&mut stored.as_slice(), Blake2b256::new(), false),
+                         Err(MerkleError::Deserialization(_))));
+    }
+
+    #[test]
+    fn save_and_load_unbuilt_tree() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::default();
+        merkle_tree.push(&"a").unwrap();
+        merkle_tree.push(&"b").unwrap();
+        let mut stored = Vec::new();
+        merkle_tree.save(&mut stored).unwrap();
+        let loaded = MerkleTree::load(&mut stored.as_slice(), Sha256::new(), true).unwrap();
+        assert_eq!(merkle_tree, loaded);
+        assert_eq!(Err(MerkleError::NotBuilt), loaded.get_merkle_root());
+    }
+
+    #[test]
+    fn load_corrupted_tree() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                                           SerializationFormat::Json).unwrap();
+        merkle_tree.build().unwrap();
+        merkle_tree.layers[1][1][0] ^= 1;
+        let mut stored = Vec::new();
+        merkle_tree.save(&mut stored).unwrap();
+        assert_eq!(Err(MerkleError::Corrupted { layer: 1, index: 1 }),
+                   MerkleTree::load(&mut stored.as_slice(), Sha256::new(), true));
+        assert_eq!(Ok(merkle_tree),
+                   MerkleTree::load(&mut stored.as_slice(), Sha256::new(), false));
+    }
+
+    #[test]
+    fn load_invalid_data() {
+        let _ = env_logger::init();
+        let mut merkle_tree: MerkleTree = MerkleTree::from(&["a", "b"], SerializationFormat::Json)
+            .unwrap();
+        merkle_tree.build().unwrap();
+        let mut stored = Vec::new();
+        merkle_tree.save(&mut stored).unwrap();
+
+        let mut bad_magic = stored.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(Err(MerkleError::Deserialization("Bad magic".to_string())),
+                   MerkleTree::load(&mut bad_magic.as_slice(), Sha256::new(), false));
+
+        let mut bad_version = stored.clone();
+        bad_version[4] = 2;
+        assert_eq!(Err(MerkleError::Deserialization("Unsupported version 2".to_string())),
+                   MerkleTree::load(&mut bad_version.as_slice(), Sha256::new(), false));
+
+        let truncated = &stored[..stored.len() - 1];
+        assert!(matches!(MerkleTree::load(&mut &truncated[..], Sha256::new(), false),
+                         Err(MerkleError::Io(_))));
+    }
+}
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use odd_node_policy::OddNodePolicy;
+use serialization_format::SerializationFormat;
+use hash_function::{hash_leaf, hash_node, hash_lonely, hash_single};
+
+/// Положение соседнего узла относительно узла, лежащего на пути от "листа" к корню.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Position {
+    /// Соседний узел находится слева.
+    Left,
+    /// Соседний узел находится справа.
+    Right,
+}
+
+/// Шаг доказательства включения: хэш соседнего узла и его положение.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ProofNode<N> {
+    /// Хэш соседнего узла.
+    pub hash: N,
+    /// Положение соседнего узла.
+    pub position: Position,
+}
+
+/// Доказательство включения "листа" в дерево (audit proof).
+///
+/// Содержит хэши соседних узлов на каждом слое, начиная со слоя "листьев". Слои, на которых узел
+/// оказался последним и не получил пары, в `path` не попадают - родитель такого узла
+/// определяется правилом `odd_node_policy`, а сами такие слои вычисляются по `leaf_index`
+/// и `tree_size`.
+#[derive(Clone)]
+pub struct Proof<D: Hasher> {
+    /// Индекс "листа" в 0-ом слое дерева.
+    pub leaf_index: usize,
+    /// Количество "листьев" в дереве на момент построения доказательства.
+    pub tree_size: usize,
+    /// Соседние узлы на пути от "листа" к корню.
+    pub path: Vec<ProofNode<D::Node>>,
+    /// Хэш-функция дерева.
+    pub digest: D,
+    /// Режим хэширования узлов дерева.
+    pub mode: TreeMode,
+    /// Правило обработки узла, которому не нашлось пары в конце слоя.
+    pub odd_node_policy: OddNodePolicy,
+}
+
+impl_debug_eq!(Proof { leaf_index, tree_size, path, mode, odd_node_policy });
+
+impl<D: Hasher> Proof<D> {
+    /// Вычисляет корень дерева по доказательству для переданного хэша "листа".
+    /// Возвращает `None`, если доказательство не согласуется с `leaf_index` и `tree_size`:
+    /// не хватает или остались лишние узлы, либо положение соседнего узла не совпадает с
+    /// ожидаемым.
+    pub fn root(&self, leaf_hash: &D::Node) -> Option<D::Node> {
+        if self.leaf_index >= self.tree_size {
+            return None;
+        }
+        if self.tree_size == 1 {
+            return if self.path.is_empty() {
+                Some(hash_single(&self.digest, self.mode, self.odd_node_policy, leaf_hash))
+            } else {
+                None
+            };
+        }
+        let mut path = self.path.iter();
+        let mut hash = *leaf_hash;
+        let mut index = self.leaf_index;
+        let mut layer_size = self.tree_size;
+        while layer_size > 1 {
+            hash = if !index.is_multiple_of(2) {
+                match path.next() {
+                    Some(&ProofNode { hash: ref sibling, position: Position::Left }) => {
+                        hash_node(&self.digest, self.mode, sibling, &hash)
+                    }
+                    _ => return None,
+                }
+            } else if index + 1 < layer_size {
+                match path.next() {
+                    Some(&ProofNode { hash: ref sibling, position: Position::Right }) => {
+                        hash_node(&self.digest, self.mode, &hash, sibling)
+                    }
+                    _ => return None,
+                }
+            } else {
+                hash_lonely(&self.digest, self.mode, self.odd_node_policy, &hash)
+            };
+            index /= 2;
+            layer_size = layer_size.div_ceil(2);
+        }
+        match path.next() {
+            Some(_) => None,
+            None => Some(hash),
+        }
+    }
+
+    /// Проверяет, что "лист" с переданным хэшем входит в дерево с корнем `root`.
+    /// Для проверки не требуется само дерево.
+    pub fn verify(&self, root: &D::Node, leaf_hash: &D::Node) -> bool {
+        self.root(leaf_hash).as_ref() == Some(root)
+    }
+
+    /// Проверяет, что переданное значение входит в дерево с корнем `root`.
+    /// Значение сериализуется в указанном формате и хэшируется так же, как при добавлении в дерево.
+    /// Если значение не удалось сериализовать, оно не может входить в дерево и проверка не проходит.
+    pub fn verify_value<Serializable>(&self,
+                                      root: &D::Node,
+                                      value: &Serializable,
+                                      format: &SerializationFormat)
+                                      -> bool
+        where Serializable: Serialize
+    {
+        match format.serialize(value) {
+            Ok(serialized_value) => {
+                let leaf_hash = hash_leaf(&self.digest, self.mode, &serialized_value);
+                self.verify(root, &leaf_hash)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate env_logger;
+    use super::{Position, Proof, ProofNode};
+    use crypto::sha2::Sha256;
+    use merkle_tree::MerkleTree;
+    use serialization_format::SerializationFormat;
+    use odd_node_policy::OddNodePolicy;
+    use tree_mode::TreeMode;
+    use hash_function::hash_leaf;
+    use test_helpers::{ODD_NODE_POLICIES, hash_from_hex};
+
+    #[test]
+    fn verify_all_leaves() {
+        let _ = env_logger::init();
+        for policy in ODD_NODE_POLICIES.iter() {
+            for size in 1..12 {
+                let leaves: Vec<usize> = (0..size).collect();
+                let mut merkle_tree = MerkleTree::from(&leaves, SerializationFormat::Bincode).unwrap();
+                merkle_tree.odd_node_policy = *policy;
+                let root = merkle_tree.build().unwrap();
+                for (leaf, leaf_hash) in leaves.iter().zip(merkle_tree.layers[0].clone().iter()) {
+                    let proof = merkle_tree.audit_proof(leaf_hash).unwrap();
+                    assert!(proof.verify(&root, leaf_hash));
+                    assert!(proof.verify_value(&root, leaf, &SerializationFormat::Bincode));
+                    assert!(!proof.verify_value(&root, &(size + 1), &SerializationFormat::Bincode));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reject_tampered_proof() {
+        let _ = env_logger::init();
+        let mut merkle_tree = MerkleTree::from(&["a", "b", "c", "d", "e"],
+                                               SerializationFormat::Json).unwrap();
+        let root = merkle_tree.build().unwrap();
+        let leaf_hash = merkle_tree.layers[0][2];
+        let proof = merkle_tree.audit_proof(&leaf_hash).unwrap();
+        assert!(proof.verify(&root, &leaf_hash));
+        assert!(!proof.verify(&root, &merkle_tree.layers[0][3]));
+        assert!(!proof.verify(&merkle_tree.layers[1][0], &leaf_hash));
+        let mut wrong_position = proof.clone();
+        wrong_position.path[0].position = Position::Left;
+        assert_eq!(None, wrong_position.root(&leaf_hash));
+        let mut short_path = proof.clone();
+        short_path.path.pop();
+        assert_eq!(None, short_path.root(&leaf_hash));
+        let mut long_path = proof.clone();
+        long_path.path.push(ProofNode { hash: root, position: Position::Right });
+        assert_eq!(None, long_path.root(&leaf_hash));
+        let mut wrong_index = proof.clone();
+        wrong_index.leaf_index = 5;
+        assert_eq!(None, wrong_index.root(&leaf_hash));
+        let mut wrong_size = proof.clone();
+        wrong_size.tree_size = 3;
+        assert_eq!(None, wrong_size.root(&leaf_hash));
+    }
+
+    #[test]
+    fn verify_rfc6962_inclusion_without_tree() {
+        let _ = env_logger::init();
+        let proof = Proof {
+            leaf_index: 0,
+            tree_size: 8,
+            path: vec![
+                ProofNode {
+                    hash: hash_from_hex("96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7"),
+                    position: Position::Right,
+                },
+                ProofNode {
+                    hash: hash_from_hex("5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e"),
+                    position: Position::Right,
+                },
+                ProofNode {
+                    hash: hash_from_hex("6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4"),
+                    position: Position::Right,
+                },
+            ],
+            digest: Sha256::new(),
+            mode: TreeMode::Rfc6962,
+            odd_node_policy: OddNodePolicy::Promote,
+        };
+        let root = hash_from_hex("5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328");
+        let leaf_hash = hash_leaf(&Sha256::new(), TreeMode::Rfc6962, b"");
+        assert!(proof.verify(&root, &leaf_hash));
+        assert!(!proof.verify(&root, &hash_leaf(&Sha256::new(), TreeMode::Classic, b"")));
+    }
+}
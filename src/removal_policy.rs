@@ -0,0 +1,12 @@
+/// Правило удаления "листа" из дерева методом `MerkleTree::remove`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum RemovalPolicy {
+    /// "Лист" заменяется узлом, заполненным нулями. Индексы остальных "листьев" и их
+    /// доказательства включения относительно нового корня не меняются, пересчитывается только
+    /// путь от удалённого "листа" до корня.
+    #[default]
+    Tombstone,
+    /// "Лист" удаляется из слоя, все последующие "листья" сдвигаются на одну позицию влево.
+    /// Пересчитываются все узлы правее удалённого "листа".
+    Shift,
+}
@@ -0,0 +1,231 @@
+use merkle_error::MerkleError;
+
+/// Элемент RLP (Recursive Length Prefix) - формата сериализации Ethereum: строка байт или список
+/// элементов.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RlpItem {
+    /// Строка байт.
+    Bytes(Vec<u8>),
+    /// Список элементов.
+    List(Vec<RlpItem>),
+}
+
+impl RlpItem {
+    /// Строка байт, содержащая целое число в big-endian без ведущих нулей. Ноль кодируется пустой
+    /// строкой.
+    pub fn uint(value: u64) -> RlpItem {
+        let bytes = value.to_be_bytes();
+        let leading_zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+        RlpItem::Bytes(bytes[leading_zeros..].to_vec())
+    }
+
+    /// Возвращает содержимое строки байт или `None` для списка.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            RlpItem::Bytes(ref bytes) => Some(bytes),
+            RlpItem::List(_) => None,
+        }
+    }
+
+    /// Возвращает элементы списка или `None` для строки байт.
+    pub fn as_list(&self) -> Option<&[RlpItem]> {
+        match *self {
+            RlpItem::Bytes(_) => None,
+            RlpItem::List(ref items) => Some(items),
+        }
+    }
+
+    /// Возвращает целое число, записанное в строке байт.
+    ///
+    /// # Failures
+    /// В случае, если элемент - список, число записано с ведущими нулями или не помещается в `u64`
+    /// возвращается `MerkleError::Deserialization`.
+    pub fn to_uint(&self) -> Result<u64, MerkleError> {
+        match self.as_bytes() {
+            Some(bytes) if bytes.len() <= 8 && bytes.first() != Some(&0) => {
+                Ok(bytes.iter().fold(0, |value, &byte| value << 8 | byte as u64))
+            }
+            _ => Err(rlp_error("Invalid integer")),
+        }
+    }
+
+    /// Кодирует элемент в RLP.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        self.encode_to(&mut encoded);
+        encoded
+    }
+
+    /// Декодирует единственный элемент, занимающий все переданные байты.
+    ///
+    /// # Failures
+    /// В случае, если данные обрываются, содержат лишние байты после элемента или закодированы не
+    /// кратчайшим способом возвращается `MerkleError::Deserialization`.
+    pub fn decode(data: &[u8]) -> Result<RlpItem, MerkleError> {
+        let (item, consumed) = decode_item(data)?;
+        if consumed != data.len() {
+            return Err(rlp_error("Trailing bytes after item"));
+        }
+        Ok(item)
+    }
+
+    /// Дописывает кодировку элемента в конец `encoded`.
+    fn encode_to(&self, encoded: &mut Vec<u8>) {
+        match *self {
+            RlpItem::Bytes(ref bytes) => {
+                if bytes.len() == 1 && bytes[0] < 0x80 {
+                    encoded.push(bytes[0]);
+                } else {
+                    encode_length(encoded, bytes.len(), 0x80);
+                    encoded.extend_from_slice(bytes);
+                }
+            }
+            RlpItem::List(ref items) => {
+                let mut payload = Vec::new();
+                for item in items {
+                    item.encode_to(&mut payload);
+                }
+                encode_length(encoded, payload.len(), 0xc0);
+                encoded.extend_from_slice(&payload);
+            }
+        }
+    }
+}
+
+/// Записывает префикс длины: для длин до 55 байт - `offset + len`, для больших - `offset + 55 +`
+/// количество байт длины, за которым следует сама длина в big-endian.
+fn encode_length(encoded: &mut Vec<u8>, len: usize, offset: u8) {
+    if len <= 55 {
+        encoded.push(offset + len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let leading_zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+        encoded.push(offset + 55 + (bytes.len() - leading_zeros) as u8);
+        encoded.extend_from_slice(&bytes[leading_zeros..]);
+    }
+}
+
+/// Декодирует элемент в начале `data`. Возвращает элемент и количество занятых им байт.
+fn decode_item(data: &[u8]) -> Result<(RlpItem, usize), MerkleError> {
+    let prefix = *data.first().ok_or_else(|| rlp_error("Unexpected end of data"))?;
+    match prefix {
+        0x00..=0x7f => Ok((RlpItem::Bytes(vec![prefix]), 1)),
+        0x80..=0xbf => {
+            let (offset, len) = decode_length(data, 0x80)?;
+            let bytes = &data[offset..offset + len];
+            if len == 1 && bytes[0] < 0x80 {
+                return Err(rlp_error("Single byte is not encoded as itself"));
+            }
+            Ok((RlpItem::Bytes(bytes.to_vec()), offset + len))
+        }
+        _ => {
+            let (offset, len) = decode_length(data, 0xc0)?;
+            let mut payload = &data[offset..offset + len];
+            let mut items = Vec::new();
+            while !payload.is_empty() {
+                let (item, consumed) = decode_item(payload)?;
+                items.push(item);
+                payload = &payload[consumed..];
+            }
+            Ok((RlpItem::List(items), offset + len))
+        }
+    }
+}
+
+/// Разбирает префикс длины. Возвращает размер префикса и длину содержимого, проверяя, что
+/// содержимое помещается в `data`.
+fn decode_length(data: &[u8], offset: u8) -> Result<(usize, usize), MerkleError> {
+    let short_len = (data[0] - offset) as usize;
+    let (header_len, len) = if short_len <= 55 {
+        (1, short_len)
+    } else {
+        let len_len = short_len - 55;
+        if len_len > 8 || data.len() < 1 + len_len {
+            return Err(rlp_error("Invalid length prefix"));
+        }
+        let len_bytes = &data[1..1 + len_len];
+        if len_bytes[0] == 0 {
+            return Err(rlp_error("Length has leading zeros"));
+        }
+        let len = len_bytes.iter().fold(0u64, |len, &byte| len << 8 | byte as u64);
+        if len <= 55 {
+            return Err(rlp_error("Short length encoded in long form"));
+        }
+        (1 + len_len, len as usize)
+    };
+    if (data.len() - header_len) < len {
+        return Err(rlp_error("Unexpected end of data"));
+    }
+    Ok((header_len, len))
+}
+
+fn rlp_error(message: &str) -> MerkleError {
+    MerkleError::Deserialization(format!("Invalid RLP: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use super::RlpItem;
+    use merkle_error::MerkleError;
+
+    fn bytes(value: &str) -> RlpItem {
+        RlpItem::Bytes(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn encode_known_vectors() {
+        let _ = env_logger::init();
+        assert_eq!(vec![0x83, b'd', b'o', b'g'], bytes("dog").encode());
+        assert_eq!(vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g'],
+                   RlpItem::List(vec![bytes("cat"), bytes("dog")]).encode());
+        assert_eq!(vec![0x80], bytes("").encode());
+        assert_eq!(vec![0xc0], RlpItem::List(Vec::new()).encode());
+        assert_eq!(vec![0x80], RlpItem::uint(0).encode());
+        assert_eq!(vec![0x0f], RlpItem::uint(15).encode());
+        assert_eq!(vec![0x82, 0x04, 0x00], RlpItem::uint(1024).encode());
+
+        let empty = RlpItem::List(Vec::new());
+        let set_theory = RlpItem::List(vec![empty.clone(),
+                                            RlpItem::List(vec![empty.clone()]),
+                                            RlpItem::List(vec![empty.clone(),
+                                                               RlpItem::List(vec![empty])])]);
+        assert_eq!(vec![0xc7, 0xc0, 0xc1, 0xc0, 0xc3, 0xc0, 0xc1, 0xc0], set_theory.encode());
+
+        let lorem = bytes("Lorem ipsum dolor sit amet, consectetur adipisicing elit");
+        let encoded = lorem.encode();
+        assert_eq!(vec![0xb8, 0x38], encoded[..2].to_vec());
+        assert_eq!(58, encoded.len());
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let _ = env_logger::init();
+        let long_list = RlpItem::List((0..100).map(RlpItem::uint).collect());
+        for item in &[bytes("dog"),
+                      bytes(""),
+                      RlpItem::uint(0x7f),
+                      RlpItem::uint(0x80),
+                      RlpItem::Bytes(vec![0xab; 1024]),
+                      long_list] {
+            assert_eq!(Ok(item.clone()), RlpItem::decode(&item.encode()));
+        }
+        assert_eq!(Ok(1024), RlpItem::uint(1024).to_uint());
+    }
+
+    #[test]
+    fn reject_malformed() {
+        let _ = env_logger::init();
+        for data in &[vec![],
+                      vec![0x83, b'd', b'o'],
+                      vec![0x83, b'd', b'o', b'g', 0x00],
+                      vec![0x81, 0x05],
+                      vec![0xb8, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05],
+                      vec![0xb9, 0x00, 0x38],
+                      vec![0xc2, 0x83, b'd']] {
+            assert!(matches!(RlpItem::decode(data), Err(MerkleError::Deserialization(_))));
+        }
+        assert!(RlpItem::Bytes(vec![0, 1]).to_uint().is_err());
+    }
+}
@@ -2,9 +2,10 @@ use bincode;
 use serde_json;
 use rmp_serde;
 use serde::Serialize;
+use merkle_error::MerkleError;
 
 /// Перечисление для хранения типа формата сериализации.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum SerializationFormat {
     /// JSON
     Json,
@@ -16,13 +17,20 @@ pub enum SerializationFormat {
 
 impl SerializationFormat {
     /// Сериализует переданное значение в зависимости от типа выбранного формата сериализации.
-    pub fn serialize<SerializableType>(&self, value: &SerializableType) -> Vec<u8>
+    ///
+    /// # Failures
+    /// В случае, если значение не удалось сериализовать возвращает `MerkleError::Serialization`
+    /// с описанием ошибки сериализатора.
+    pub fn serialize<SerializableType>(&self, value: &SerializableType) -> Result<Vec<u8>, MerkleError>
         where SerializableType: Serialize
     {
-        match self {
-            &SerializationFormat::Json => serde_json::to_string(&value).unwrap().into_bytes(),
-            &SerializationFormat::MsgPack => rmp_serde::to_vec(&value).unwrap(),
-            &SerializationFormat::Bincode => bincode::serialize(&value, bincode::Infinite).unwrap(),
+        match *self {
+            SerializationFormat::Json => serde_json::to_vec(&value)
+                .map_err(|error| MerkleError::Serialization(error.to_string())),
+            SerializationFormat::MsgPack => rmp_serde::to_vec(&value)
+                .map_err(|error| MerkleError::Serialization(error.to_string())),
+            SerializationFormat::Bincode => bincode::serialize(&value, bincode::Infinite)
+                .map_err(|error| MerkleError::Serialization(error.to_string())),
         }
     }
 }
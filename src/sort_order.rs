@@ -0,0 +1,12 @@
+/// Порядок "листьев" отсортированного дерева.
+/// Должен быть выбран до того, как в дерево будут добавлены "листья".
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SortOrder {
+    /// "Листья" упорядочены по хэшу сериализованного значения. Подходит для множеств значений,
+    /// например списков отзыва.
+    #[default]
+    ByHash,
+    /// "Листья" упорядочены по ключу, переданному вместе со значением. Каждый "лист" - хэш пары из
+    /// хэшей ключа и значения.
+    ByKey,
+}
@@ -0,0 +1,506 @@
+use std::fmt;
+use serde::Serialize;
+use crypto::sha2::Sha256;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use sort_order::SortOrder;
+use odd_node_policy::OddNodePolicy;
+use removal_policy::RemovalPolicy;
+use serialization_format::SerializationFormat;
+use hash_function::hash_leaf;
+use merkle_tree::MerkleTree;
+use sorted_proof::{SortedLeafProof, NonMembershipProof};
+use merkle_error::MerkleError;
+
+/// Префикс данных, из которых вычисляется корень отсортированного дерева. Отличается от
+/// префиксов "листьев" и узлов RFC 6962.
+const SORTED_ROOT_PREFIX: u8 = 0x02;
+
+/// "Лист" отсортированного дерева: ключ, по которому он упорядочен, и сериализованное значение.
+/// При упорядочивании по хэшу ключом являются байты хэша "листа".
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SortedLeaf {
+    /// Ключ "листа".
+    pub key: Vec<u8>,
+    /// Сериализованное значение.
+    pub value: Vec<u8>,
+}
+
+impl SortedLeaf {
+    /// Вычисляет хэш, под которым "лист" хранится в дереве: хэш RFC 6962 от значения при
+    /// упорядочивании по хэшу, либо от длины ключа (u64, big-endian), ключа и значения при
+    /// упорядочивании по ключу. Префикс 0x00 не позволяет выдать внутренний узел за "лист".
+    pub fn leaf_hash<D: Hasher>(&self, digest: &D, order: SortOrder) -> D::Node {
+        match order {
+            SortOrder::ByHash => hash_leaf(digest, TreeMode::Rfc6962, &self.value),
+            SortOrder::ByKey => {
+                let mut data = Vec::with_capacity(8 + self.key.len() + self.value.len());
+                data.extend_from_slice(&(self.key.len() as u64).to_be_bytes());
+                data.extend_from_slice(&self.key);
+                data.extend_from_slice(&self.value);
+                hash_leaf(digest, TreeMode::Rfc6962, &data)
+            }
+        }
+    }
+}
+
+/// Вычисляет корень отсортированного дерева из корня дерева "листьев": хэш префикса
+/// `SORTED_ROOT_PREFIX`, порядка "листьев", их количества (u64, big-endian) и корня. Так
+/// доказательство не может подменить ни размер дерева, ни способ хэширования "листьев".
+pub fn sorted_root<D: Hasher>(digest: &D, order: SortOrder, tree_size: usize, root: &D::Node) -> D::Node {
+    let order_byte = match order {
+        SortOrder::ByHash => 0,
+        SortOrder::ByKey => 1,
+    };
+    let mut data = vec![SORTED_ROOT_PREFIX, order_byte];
+    data.extend_from_slice(&(tree_size as u64).to_be_bytes());
+    data.extend_from_slice(root.as_ref());
+    hash_leaf(digest, TreeMode::Classic, &data)
+}
+
+/// Корень пустого отсортированного дерева: `sorted_root` от хэша пустого дерева RFC 6962 (хэша
+/// пустой строки) и нулевого размера.
+pub fn empty_sorted_root<D: Hasher>(digest: &D, order: SortOrder) -> D::Node {
+    sorted_root(digest, order, 0, &hash_leaf(digest, TreeMode::Classic, &[]))
+}
+
+/// Merkle tree, "листья" которого упорядочены по хэшу значения или по ключу, а ключи не
+/// повторяются.
+///
+/// Кроме доказательств включения позволяет доказать отсутствие ключа: соседние "листья", между
+/// которыми должен был бы оказаться ключ, подтверждаются доказательствами включения, а их
+/// соседство следует из индексов (смотри `NonMembershipProof`). Дерево "листьев" строится в
+/// режиме RFC 6962, а его размер и порядок "листьев" входят в корень (смотри `sorted_root`).
+/// Дерево всегда построено, вставка и удаление пересчитывают только узлы правее изменённого
+/// "листа".
+pub struct SortedMerkleTree<D: Hasher = Sha256> {
+    /// Формат сериализации значений перед тем как их хэшировать.
+    pub format: SerializationFormat,
+    /// Порядок "листьев". Меняется только до добавления "листьев".
+    pub order: SortOrder,
+    tree: MerkleTree<D>,
+    leaves: Vec<SortedLeaf>,
+}
+
+impl Default for SortedMerkleTree<Sha256> {
+    fn default() -> SortedMerkleTree<Sha256> {
+        SortedMerkleTree::with_digest(Sha256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for SortedMerkleTree<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SortedMerkleTree")
+            .field("format", &self.format)
+            .field("order", &self.order)
+            .field("leaves", &self.leaves)
+            .finish()
+    }
+}
+
+impl<D: Hasher> SortedMerkleTree<D> {
+    /// Создаёт пустое дерево, использующее переданную хэш-функцию.
+    pub fn with_digest(digest: D) -> SortedMerkleTree<D> {
+        SortedMerkleTree {
+            format: SerializationFormat::MsgPack,
+            order: SortOrder::ByHash,
+            tree: MerkleTree {
+                mode: TreeMode::Rfc6962,
+                odd_node_policy: OddNodePolicy::Promote,
+                removal_policy: RemovalPolicy::Shift,
+                ..MerkleTree::with_digest(digest)
+            },
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Количество "листьев".
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Проверяет, есть ли в дереве "листья".
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Дерево, в котором хранятся хэши "листьев" в отсортированном порядке.
+    pub fn tree(&self) -> &MerkleTree<D> {
+        &self.tree
+    }
+
+    /// "Листья" в отсортированном порядке.
+    pub fn leaves(&self) -> &[SortedLeaf] {
+        &self.leaves
+    }
+
+    /// Возвращает корень дерева, вычисленный функцией `sorted_root`. Для дерева без "листьев" -
+    /// `empty_sorted_root`.
+    ///
+    /// # Failures
+    /// Смотри `MerkleTree::get_merkle_root`.
+    pub fn root(&self) -> Result<D::Node, MerkleError> {
+        if self.leaves.is_empty() {
+            return Ok(empty_sorted_root(&self.tree.digest, self.order));
+        }
+        let root = self.tree.get_merkle_root()?;
+        Ok(sorted_root(&self.tree.digest, self.order, self.leaves.len(), &root))
+    }
+
+    /// Сериализует значение и возвращает хэш его "листа" - ключ значения при упорядочивании по
+    /// хэшу.
+    ///
+    /// # Failures
+    /// В случае, если значение не удалось сериализовать возвращается `MerkleError::Serialization`.
+    pub fn value_key<Serializable>(&self, value: &Serializable) -> Result<Vec<u8>, MerkleError>
+        where Serializable: Serialize
+    {
+        Ok(self.hashed_leaf(value)?.key)
+    }
+
+    /// Добавляет значение в дерево, упорядоченное по хэшу. Возвращает `false`, если такое значение
+    /// уже было в дереве.
+    ///
+    /// # Failures
+    /// В случае, если дерево упорядочено по ключу возвращается `MerkleError::UnsupportedOrder`,
+    /// если значение не удалось сериализовать - `MerkleError::Serialization`.
+    pub fn insert<Serializable>(&mut self, value: &Serializable) -> Result<bool, MerkleError>
+        where Serializable: Serialize
+    {
+        if self.order != SortOrder::ByHash {
+            return Err(MerkleError::UnsupportedOrder(self.order));
+        }
+        let leaf = self.hashed_leaf(value)?;
+        self.insert_leaf(leaf)
+    }
+
+    /// Добавляет значение с ключом в дерево, упорядоченное по ключу, заменяя значение, если ключ уже
+    /// был в дереве. Возвращает `false`, если ключ уже был в дереве.
+    ///
+    /// # Failures
+    /// В случае, если дерево упорядочено по хэшу возвращается `MerkleError::UnsupportedOrder`,
+    /// если значение не удалось сериализовать - `MerkleError::Serialization`.
+    pub fn insert_with_key<Serializable>(&mut self, key: &[u8], value: &Serializable) -> Result<bool, MerkleError>
+        where Serializable: Serialize
+    {
+        if self.order != SortOrder::ByKey {
+            return Err(MerkleError::UnsupportedOrder(self.order));
+        }
+        let value = self.format.serialize(value)?;
+        self.insert_leaf(SortedLeaf {
+            key: key.to_vec(),
+            value,
+        })
+    }
+
+    /// Удаляет "лист" с переданным ключом. Возвращает `false`, если такого ключа не было.
+    ///
+    /// # Failures
+    /// Смотри `MerkleTree::remove`.
+    pub fn remove_key(&mut self, key: &[u8]) -> Result<bool, MerkleError> {
+        match self.search(key) {
+            Ok(index) => {
+                self.tree.remove(index)?;
+                self.leaves.remove(index);
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Проверяет, есть ли в дереве "лист" с переданным ключом.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.search(key).is_ok()
+    }
+
+    /// Возвращает доказательство включения "листа" с переданным ключом.
+    ///
+    /// # Failures
+    /// В случае, если ключа нет в дереве возвращается `MerkleError::LeafNotFound`.
+    pub fn membership_proof(&self, key: &[u8]) -> Result<SortedLeafProof<D>, MerkleError> {
+        match self.search(key) {
+            Ok(index) => self.leaf_proof(index),
+            Err(_) => Err(MerkleError::LeafNotFound),
+        }
+    }
+
+    /// Возвращает доказательство отсутствия ключа: доказательства включения соседних "листьев",
+    /// между которыми оказался бы ключ. Для ключа меньше первого или больше последнего "листа"
+    /// доказывается включение только крайнего "листа", для пустого дерева доказательство не
+    /// содержит "листьев".
+    ///
+    /// # Failures
+    /// В случае, если ключ присутствует в дереве возвращается `MerkleError::LeafExists`.
+    pub fn non_membership_proof(&self, key: &[u8]) -> Result<NonMembershipProof<D>, MerkleError> {
+        match self.search(key) {
+            Ok(_) => Err(MerkleError::LeafExists),
+            Err(index) => {
+                let left = if index > 0 {
+                    Some(self.leaf_proof(index - 1)?)
+                } else {
+                    None
+                };
+                let right = if index < self.leaves.len() {
+                    Some(self.leaf_proof(index)?)
+                } else {
+                    None
+                };
+                Ok(NonMembershipProof {
+                    left,
+                    right,
+                    order: self.order,
+                    digest: self.tree.digest.clone(),
+                })
+            }
+        }
+    }
+
+    /// Вставляет "лист" на его место в порядке ключей и пересчитывает дерево.
+    fn insert_leaf(&mut self, leaf: SortedLeaf) -> Result<bool, MerkleError> {
+        let hash = leaf.leaf_hash(&self.tree.digest, self.order);
+        match self.search(&leaf.key) {
+            Ok(index) => {
+                if self.leaves[index] != leaf {
+                    self.tree.update_hash(index, hash)?;
+                    self.leaves[index] = leaf;
+                }
+                Ok(false)
+            }
+            Err(index) => {
+                self.tree.insert_hash(index, hash)?;
+                if !self.tree.builded {
+                    self.tree.build()?;
+                }
+                self.leaves.insert(index, leaf);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Доказательство включения "листа" с переданным индексом.
+    fn leaf_proof(&self, index: usize) -> Result<SortedLeafProof<D>, MerkleError> {
+        Ok(SortedLeafProof {
+            leaf: self.leaves[index].clone(),
+            order: self.order,
+            proof: self.tree.proof_by_index(index)?,
+        })
+    }
+
+    /// Ищет ключ среди "листьев". Возвращает индекс "листа" либо позицию, на которой он должен
+    /// находиться.
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        self.leaves.binary_search_by(|leaf| leaf.key.as_slice().cmp(key))
+    }
+
+    /// Сериализует значение и создаёт "лист" с ключом, равным хэшу "листа".
+    fn hashed_leaf<Serializable>(&self, value: &Serializable) -> Result<SortedLeaf, MerkleError>
+        where Serializable: Serialize
+    {
+        let value = self.format.serialize(value)?;
+        let key = hash_leaf(&self.tree.digest, TreeMode::Rfc6962, &value).as_ref().to_vec();
+        Ok(SortedLeaf { key, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use crypto::sha2::Sha256;
+    use super::{SortedMerkleTree, SortedLeaf, empty_sorted_root};
+    use sorted_proof::{SortedLeafProof, NonMembershipProof};
+    use proof::{Proof, ProofNode, Position};
+    use merkle_tree::MerkleTree;
+    use tree_mode::TreeMode;
+    use odd_node_policy::OddNodePolicy;
+    use sort_order::SortOrder;
+    use serialization_format::SerializationFormat;
+    use merkle_error::MerkleError;
+
+    fn revocation_list(serials: &[u64]) -> SortedMerkleTree {
+        let mut sorted_tree = SortedMerkleTree::default();
+        for serial in serials {
+            sorted_tree.insert(serial).unwrap();
+        }
+        sorted_tree
+    }
+
+    #[test]
+    fn insertion_order_does_not_matter() {
+        let _ = env_logger::init();
+        let sorted_tree = revocation_list(&[5, 3, 9, 1, 7]);
+        assert_eq!(revocation_list(&[1, 3, 5, 7, 9]).root(), sorted_tree.root());
+        assert_eq!(5, sorted_tree.len());
+        let keys: Vec<&[u8]> = sorted_tree.leaves().iter().map(|leaf| leaf.key.as_slice()).collect();
+        let mut sorted_keys = keys.clone();
+        sorted_keys.sort();
+        assert_eq!(sorted_keys, keys);
+
+        let mut rebuilt = MerkleTree {
+            mode: TreeMode::Rfc6962,
+            odd_node_policy: OddNodePolicy::Promote,
+            ..MerkleTree::default()
+        };
+        for leaf in sorted_tree.leaves() {
+            rebuilt.push_hash(leaf.leaf_hash(&Sha256::new(), SortOrder::ByHash));
+        }
+        assert_eq!(rebuilt.build(), sorted_tree.tree().get_merkle_root());
+
+        let mut with_duplicate = revocation_list(&[5, 3, 9, 1, 7]);
+        assert!(!with_duplicate.insert(&3u64).unwrap());
+        assert_eq!(sorted_tree.root(), with_duplicate.root());
+    }
+
+    #[test]
+    fn absence_proofs_by_hash() {
+        let _ = env_logger::init();
+        let serials: Vec<u64> = (0..20).map(|serial| serial * 3).collect();
+        let sorted_tree = revocation_list(&serials);
+        let root = sorted_tree.root().unwrap();
+        for serial in 0..60u64 {
+            let key = sorted_tree.value_key(&serial).unwrap();
+            if serial % 3 == 0 {
+                let proof = sorted_tree.membership_proof(&key).unwrap();
+                assert!(proof.verify(&root));
+                assert_eq!(Err(MerkleError::LeafExists), sorted_tree.non_membership_proof(&key));
+            } else {
+                let proof = sorted_tree.non_membership_proof(&key).unwrap();
+                assert!(proof.verify(&root, &key));
+                assert!(proof.verify_value(&root, &serial, &SerializationFormat::MsgPack));
+                assert!(!proof.verify_value(&root, &(serial - serial % 3), &SerializationFormat::MsgPack));
+                assert_eq!(Err(MerkleError::LeafNotFound), sorted_tree.membership_proof(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn absence_proofs_in_empty_tree() {
+        let _ = env_logger::init();
+        let sorted_tree = SortedMerkleTree::default();
+        let root = sorted_tree.root().unwrap();
+        assert_eq!(empty_sorted_root(&Sha256::new(), SortOrder::ByHash), root);
+        let key = sorted_tree.value_key(&1u64).unwrap();
+        let proof = sorted_tree.non_membership_proof(&key).unwrap();
+        assert_eq!((None, None), (proof.left.clone(), proof.right.clone()));
+        assert!(proof.verify(&root, &key));
+        assert!(proof.verify_value(&root, &1u64, &SerializationFormat::MsgPack));
+        assert!(!proof.verify(&revocation_list(&[1]).root().unwrap(), &[0]));
+        assert!(!proof.verify(&empty_sorted_root(&Sha256::new(), SortOrder::ByKey), &key));
+    }
+
+    #[test]
+    fn absence_proofs_by_key() {
+        let _ = env_logger::init();
+        let mut sorted_tree = SortedMerkleTree {
+            order: SortOrder::ByKey,
+            ..SortedMerkleTree::default()
+        };
+        assert_eq!(Err(MerkleError::UnsupportedOrder(SortOrder::ByKey)), sorted_tree.insert(&1));
+        for key in &[b"b", b"d", b"f"] {
+            assert!(sorted_tree.insert_with_key(*key, &"revoked").unwrap());
+        }
+        let root = sorted_tree.root().unwrap();
+        assert!(!sorted_tree.insert_with_key(b"d", &"reinstated").unwrap());
+        assert!(root != sorted_tree.root().unwrap());
+        let root = sorted_tree.root().unwrap();
+
+        for key in &[b"a", b"c", b"e", b"g"] {
+            let proof = sorted_tree.non_membership_proof(*key).unwrap();
+            assert!(proof.verify(&root, *key));
+            assert!(!proof.verify(&root, b"d"));
+        }
+        let proof = sorted_tree.membership_proof(b"d").unwrap();
+        assert!(proof.verify(&root));
+        assert!(proof.proof.verify(&sorted_tree.tree().get_merkle_root().unwrap(),
+                                   &proof.leaf.leaf_hash(&proof.proof.digest, SortOrder::ByKey)));
+        let mut forged = proof.clone();
+        forged.leaf.key = b"c".to_vec();
+        assert!(!forged.verify(&root));
+
+        let mut by_hash = SortedMerkleTree::default();
+        assert_eq!(Err(MerkleError::UnsupportedOrder(SortOrder::ByHash)),
+                   by_hash.insert_with_key(b"a", &1));
+    }
+
+    #[test]
+    fn reject_non_adjacent_leaves() {
+        let _ = env_logger::init();
+        let mut sorted_tree = SortedMerkleTree {
+            order: SortOrder::ByKey,
+            ..SortedMerkleTree::default()
+        };
+        for key in &[b"b", b"d", b"f"] {
+            sorted_tree.insert_with_key(*key, &"revoked").unwrap();
+        }
+        let root = sorted_tree.root().unwrap();
+        let mut forged = sorted_tree.non_membership_proof(b"c").unwrap();
+        forged.right = sorted_tree.non_membership_proof(b"e").unwrap().right;
+        assert!(!forged.verify(&root, b"c"));
+        assert!(!forged.verify(&root, b"d"));
+
+        let mut missing_left = sorted_tree.non_membership_proof(b"c").unwrap();
+        missing_left.left = None;
+        assert!(!missing_left.verify(&root, b"c"));
+
+        assert!(sorted_tree.remove_key(b"d").unwrap());
+        assert!(!sorted_tree.remove_key(b"d").unwrap());
+        let root = sorted_tree.root().unwrap();
+        assert!(sorted_tree.non_membership_proof(b"d").unwrap().verify(&root, b"d"));
+        sorted_tree.remove_key(b"b").unwrap();
+        sorted_tree.remove_key(b"f").unwrap();
+        let empty_root = sorted_tree.root().unwrap();
+        assert_eq!(empty_sorted_root(&Sha256::new(), SortOrder::ByKey), empty_root);
+        assert!(sorted_tree.non_membership_proof(b"d").unwrap().verify(&empty_root, b"d"));
+        sorted_tree.insert_with_key(b"a", &"revoked").unwrap();
+        assert!(sorted_tree.membership_proof(b"a").unwrap().verify(&sorted_tree.root().unwrap()));
+    }
+
+    #[test]
+    fn reject_internal_node_pair() {
+        let _ = env_logger::init();
+        let sorted_tree = revocation_list(&[1, 2, 3, 4]);
+        let root = sorted_tree.root().unwrap();
+        let internal = &sorted_tree.tree().layers[1];
+        let forged_leaf = |index: usize, position: Position| {
+            SortedLeafProof {
+                leaf: SortedLeaf {
+                    key: internal[index].to_vec(),
+                    value: Vec::new(),
+                },
+                order: SortOrder::ByHash,
+                proof: Proof {
+                    leaf_index: index,
+                    tree_size: 2,
+                    path: vec![ProofNode { hash: internal[1 - index], position }],
+                    digest: Sha256::new(),
+                    mode: TreeMode::Rfc6962,
+                    odd_node_policy: OddNodePolicy::Promote,
+                },
+            }
+        };
+        let forged = NonMembershipProof {
+            left: Some(forged_leaf(0, Position::Right)),
+            right: Some(forged_leaf(1, Position::Left)),
+            order: SortOrder::ByHash,
+            digest: Sha256::new(),
+        };
+        for serial in 1..5u64 {
+            let key = sorted_tree.value_key(&serial).unwrap();
+            assert!(!forged.verify(&root, &key));
+        }
+
+        let mut classic = forged.clone();
+        for bound in classic.left.iter_mut().chain(classic.right.iter_mut()) {
+            bound.proof.mode = TreeMode::Classic;
+        }
+        assert!(!classic.verify(&root, &sorted_tree.value_key(&1u64).unwrap()));
+
+        let key = sorted_tree.value_key(&5u64).unwrap();
+        let mut resized = sorted_tree.non_membership_proof(&key).unwrap();
+        assert!(resized.verify(&root, &key));
+        for bound in resized.left.iter_mut().chain(resized.right.iter_mut()) {
+            bound.proof.tree_size += 1;
+        }
+        assert!(!resized.verify(&root, &key));
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Serialize;
+use hasher::Hasher;
+use tree_mode::TreeMode;
+use sort_order::SortOrder;
+use odd_node_policy::OddNodePolicy;
+use serialization_format::SerializationFormat;
+use hash_function::hash_leaf;
+use proof::Proof;
+use sorted_merkle_tree::{SortedLeaf, sorted_root, empty_sorted_root};
+
+/// Доказательство включения "листа" отсортированного дерева вместе с самим "листом", по которому
+/// проверяющий узнаёт его ключ.
+#[derive(Clone)]
+pub struct SortedLeafProof<D: Hasher> {
+    /// "Лист" дерева.
+    pub leaf: SortedLeaf,
+    /// Порядок "листьев" дерева.
+    pub order: SortOrder,
+    /// Доказательство включения хэша "листа".
+    pub proof: Proof<D>,
+}
+
+impl_debug_eq!(SortedLeafProof { leaf, order, proof });
+
+impl<D: Hasher> SortedLeafProof<D> {
+    /// Проверяет, что "лист" входит в дерево с корнем `root`, вычисленным `sorted_root`.
+    /// Хэш "листа" вычисляется заново из ключа и значения; при упорядочивании по хэшу так же
+    /// проверяется, что ключ совпадает с хэшем "листа". Доказательства, построенные не в режиме
+    /// RFC 6962 или с другим правилом обработки одиночных узлов, отвергаются.
+    pub fn verify(&self, root: &D::Node) -> bool {
+        if self.proof.mode != TreeMode::Rfc6962 || self.proof.odd_node_policy != OddNodePolicy::Promote {
+            return false;
+        }
+        let leaf_hash = self.leaf.leaf_hash(&self.proof.digest, self.order);
+        if self.order == SortOrder::ByHash && self.leaf.key.as_slice() != leaf_hash.as_ref() {
+            return false;
+        }
+        match self.proof.root(&leaf_hash) {
+            Some(tree_root) => {
+                sorted_root(&self.proof.digest, self.order, self.proof.tree_size, &tree_root) == *root
+            }
+            None => false,
+        }
+    }
+}
+
+/// Доказательство отсутствия ключа в отсортированном дереве.
+///
+/// Состоит из доказательств включения "листьев", между которыми оказался бы ключ: левый меньше
+/// ключа, правый больше, и их индексы соседние. Если ключ меньше всех ключей дерева, левого
+/// "листа" нет, а правый должен быть первым; если больше всех - правого нет, а левый должен быть
+/// последним. Для пустого дерева доказательство не содержит "листьев" и сверяется с
+/// `empty_sorted_root`.
+#[derive(Clone)]
+pub struct NonMembershipProof<D: Hasher> {
+    /// Доказательство для ближайшего "листа" с меньшим ключом.
+    pub left: Option<SortedLeafProof<D>>,
+    /// Доказательство для ближайшего "листа" с большим ключом.
+    pub right: Option<SortedLeafProof<D>>,
+    /// Порядок "листьев" дерева.
+    pub order: SortOrder,
+    /// Хэш-функция дерева.
+    pub digest: D,
+}
+
+impl_debug_eq!(NonMembershipProof { left, right, order });
+
+impl<D: Hasher> NonMembershipProof<D> {
+    /// Проверяет, что ключа нет в дереве с корнем `root`. Размер дерева, с которым сверяются
+    /// индексы крайних "листьев", входит в корень, поэтому подменить его нельзя.
+    pub fn verify(&self, root: &D::Node, key: &[u8]) -> bool {
+        if self.left.iter().chain(self.right.iter()).any(|bound| bound.order != self.order) {
+            return false;
+        }
+        match (self.left.as_ref(), self.right.as_ref()) {
+            (Some(left), None) => {
+                left.verify(root) && left.leaf.key.as_slice() < key &&
+                left.proof.leaf_index + 1 == left.proof.tree_size
+            }
+            (None, Some(right)) => {
+                right.verify(root) && key < right.leaf.key.as_slice() && right.proof.leaf_index == 0
+            }
+            (Some(left), Some(right)) => {
+                left.verify(root) && right.verify(root) &&
+                left.leaf.key.as_slice() < key && key < right.leaf.key.as_slice() &&
+                left.proof.tree_size == right.proof.tree_size &&
+                left.proof.leaf_index + 1 == right.proof.leaf_index
+            }
+            (None, None) => empty_sorted_root(&self.digest, self.order) == *root,
+        }
+    }
+
+    /// Проверяет, что значения нет в дереве с корнем `root`, упорядоченном по хэшу.
+    /// Значение сериализуется в указанном формате и хэшируется так же, как при добавлении в дерево.
+    /// Если значение не удалось сериализовать, проверка не проходит.
+    pub fn verify_value<Serializable>(&self,
+                                      root: &D::Node,
+                                      value: &Serializable,
+                                      format: &SerializationFormat)
+                                      -> bool
+        where Serializable: Serialize
+    {
+        if self.order != SortOrder::ByHash {
+            return false;
+        }
+        match format.serialize(value) {
+            Ok(serialized_value) => {
+                let key = hash_leaf(&self.digest, TreeMode::Rfc6962, &serialized_value);
+                self.verify(root, key.as_ref())
+            }
+            Err(_) => false,
+        }
+    }
+}
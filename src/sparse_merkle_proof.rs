@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use serde::Serialize;
+use hasher::{Hasher, Node};
+use tree_mode::TreeMode;
+use serialization_format::SerializationFormat;
+use hash_function::{hash_leaf, hash_node};
+use sparse_merkle_tree::{SparseKey, SPARSE_TREE_DEPTH, bit};
+
+/// Доказательство для "листа" разреженного дерева: включения, если "лист" не пуст, либо
+/// отсутствия значения, если пуст.
+///
+/// Соседние узлы на пути от "листа" к корню, совпадающие с корнями пустых поддеревьев, в
+/// доказательство не попадают: вместо них в `bitmap` сброшен бит, соответствующий высоте узла.
+#[derive(Clone)]
+pub struct SparseMerkleProof<D: Hasher> {
+    /// Ключ "листа".
+    pub key: SparseKey,
+    /// Хэш значения "листа" на момент построения доказательства, `None` для пустого "листа".
+    pub leaf: Option<D::Node>,
+    /// Биты высот, на которых соседний узел непуст и присутствует в `siblings`. Бит высоты `h`
+    /// расположен так же, как бит ключа, выбирающий направление на этой высоте.
+    pub bitmap: [u8; 32],
+    /// Непустые соседние узлы в порядке от "листа" к корню.
+    pub siblings: Vec<D::Node>,
+    /// Хэш-функция дерева.
+    pub digest: D,
+    /// Корни пустых поддеревьев, общие с деревом (смотри `SparseMerkleTree::with_digest`), чтобы
+    /// проверка доказательства не вычисляла их заново.
+    pub default_hashes: Arc<Vec<D::Node>>,
+}
+
+impl_debug_eq!(SparseMerkleProof { key, leaf, bitmap, siblings });
+
+impl<D: Hasher> SparseMerkleProof<D> {
+    /// Вычисляет корень дерева по доказательству для переданного хэша "листа".
+    /// Возвращает `None`, если количество узлов в `siblings` не совпадает с `bitmap` или корней
+    /// пустых поддеревьев меньше глубины дерева.
+    pub fn root(&self, leaf_hash: &D::Node) -> Option<D::Node> {
+        let mut siblings = self.siblings.iter();
+        let mut hash = *leaf_hash;
+        for height in 0..SPARSE_TREE_DEPTH {
+            let sibling = if bit(&self.bitmap, height) {
+                *siblings.next()?
+            } else {
+                *self.default_hashes.get(height)?
+            };
+            hash = if bit(&self.key, height) {
+                hash_node(&self.digest, TreeMode::Classic, &sibling, &hash)
+            } else {
+                hash_node(&self.digest, TreeMode::Classic, &hash, &sibling)
+            };
+        }
+        match siblings.next() {
+            Some(_) => None,
+            None => Some(hash),
+        }
+    }
+
+    /// Проверяет, что "лист" с ключом `key` дерева с корнем `root` имеет переданный хэш.
+    pub fn verify(&self, root: &D::Node, leaf_hash: &D::Node) -> bool {
+        self.root(leaf_hash).as_ref() == Some(root)
+    }
+
+    /// Проверяет, что "лист" с ключом `key` дерева с корнем `root` содержит переданное значение.
+    /// Значение сериализуется в указанном формате и хэшируется так же, как при добавлении в дерево.
+    /// Если значение не удалось сериализовать, оно не может входить в дерево и проверка не проходит.
+    pub fn verify_value<Serializable>(&self,
+                                      root: &D::Node,
+                                      value: &Serializable,
+                                      format: &SerializationFormat)
+                                      -> bool
+        where Serializable: Serialize
+    {
+        match format.serialize(value) {
+            Ok(serialized_value) => {
+                let leaf_hash = hash_leaf(&self.digest, TreeMode::Classic, &serialized_value);
+                self.verify(root, &leaf_hash)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Проверяет, что "лист" с ключом `key` дерева с корнем `root` пуст.
+    pub fn verify_non_inclusion(&self, root: &D::Node) -> bool {
+        self.verify(root, &D::Node::zeroed())
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use serde::Serialize;
+use crypto::sha2::Sha256;
+use serialization_format::SerializationFormat;
+use hash_function::{hash_leaf, hash_node};
+use hasher::{Hasher, Node};
+use tree_mode::TreeMode;
+use sparse_merkle_proof::SparseMerkleProof;
+use merkle_error::MerkleError;
+
+/// Глубина разреженного дерева - количество бит в ключе.
+pub const SPARSE_TREE_DEPTH: usize = 256;
+
+/// Ключ "листа" разреженного дерева - путь от корня, где старший бит выбирает ветвь у корня.
+pub type SparseKey = [u8; 32];
+
+/// Разреженное Merkle tree глубины 256, "листья" которого адресуются 256-битными ключами.
+///
+/// Пустой "лист" - узел, заполненный нулями, а корни пустых поддеревьев каждой высоты вычисляются
+/// заранее, поэтому хранятся только узлы, отличающиеся от пустых: на каждый непустой "лист"
+/// приходится не более 256 узлов. Значения сериализуются в формате `format` и хэшируются как
+/// "листья" классического дерева, узлы хэшируются функцией `hash_node`.
+pub struct SparseMerkleTree<D: Hasher = Sha256> {
+    /// Формат сериализации значений перед тем как их хэшировать.
+    pub format: SerializationFormat,
+    digest: D,
+    default_hashes: Arc<Vec<D::Node>>,
+    nodes: HashMap<(usize, SparseKey), D::Node>,
+    len: usize,
+}
+
+impl Default for SparseMerkleTree<Sha256> {
+    fn default() -> SparseMerkleTree<Sha256> {
+        SparseMerkleTree::with_digest(Sha256::new())
+    }
+}
+
+impl<D: Hasher> fmt::Debug for SparseMerkleTree<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SparseMerkleTree")
+            .field("format", &self.format)
+            .field("len", &self.len)
+            .field("root", &self.root())
+            .finish()
+    }
+}
+
+impl<D> SparseMerkleTree<D>
+    where D: Hasher
+{
+    /// Создаёт пустое дерево, использующее переданную хэш-функцию, и вычисляет корни пустых
+    /// поддеревьев.
+    pub fn with_digest(digest: D) -> SparseMerkleTree<D> {
+        SparseMerkleTree {
+            format: SerializationFormat::MsgPack,
+            default_hashes: Arc::new(default_hashes(&digest)),
+            digest,
+            nodes: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Количество непустых "листьев".
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Возвращает `true`, если в дереве нет непустых "листьев".
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Корень дерева. Для пустого дерева - корень пустого поддерева высоты 256.
+    pub fn root(&self) -> D::Node {
+        self.node(SPARSE_TREE_DEPTH, &[0; 32])
+    }
+
+    /// Хэш "листа" с переданным ключом, если он не пуст.
+    pub fn get(&self, key: &SparseKey) -> Option<D::Node> {
+        self.nodes.get(&(0, *key)).cloned()
+    }
+
+    /// Сериализует значение, хэширует его и записывает в "лист" с переданным ключом, добавляя его
+    /// или заменяя существующий. Возвращает хэш прежнего значения "листа".
+    ///
+    /// # Failures
+    /// В случае, если значение не удалось сериализовать возвращается `MerkleError::Serialization`,
+    /// дерево при этом не изменяется.
+    pub fn insert<Serializable>(&mut self, key: &SparseKey, value: &Serializable) -> Result<Option<D::Node>, MerkleError>
+        where Serializable: Serialize
+    {
+        let serialized_value = self.format.serialize(value)?;
+        let hash = hash_leaf(&self.digest, TreeMode::Classic, &serialized_value);
+        Ok(self.insert_hash(key, hash))
+    }
+
+    /// Записывает уже вычисленный хэш в "лист" с переданным ключом. Возвращает хэш прежнего
+    /// значения "листа". Хэш пустого "листа" (нулевой) не отличим от отсутствия значения,
+    /// поэтому его запись очищает "лист", как `delete`.
+    pub fn insert_hash(&mut self, key: &SparseKey, hash: D::Node) -> Option<D::Node> {
+        if hash == self.default_hashes[0] {
+            return self.delete(key);
+        }
+        let previous = self.get(key);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        self.set_leaf(key, hash);
+        previous
+    }
+
+    /// Заменяет значение существующего "листа". Возвращает хэш прежнего значения.
+    ///
+    /// # Failures
+    /// В случае, если "лист" пуст возвращается `MerkleError::LeafNotFound`, если значение не
+    /// удалось сериализовать - `MerkleError::Serialization`.
+    pub fn update<Serializable>(&mut self, key: &SparseKey, value: &Serializable) -> Result<D::Node, MerkleError>
+        where Serializable: Serialize
+    {
+        if self.get(key).is_none() {
+            return Err(MerkleError::LeafNotFound);
+        }
+        self.insert(key, value)?.ok_or(MerkleError::LeafNotFound)
+    }
+
+    /// Очищает "лист" с переданным ключом. Возвращает хэш удалённого значения, либо `None`, если
+    /// "лист" уже был пуст.
+    pub fn delete(&mut self, key: &SparseKey) -> Option<D::Node> {
+        let previous = self.get(key);
+        if previous.is_some() {
+            self.len -= 1;
+            self.set_leaf(key, self.default_hashes[0]);
+        }
+        previous
+    }
+
+    /// Возвращает доказательство для "листа" с переданным ключом. Если "лист" не пуст, это
+    /// доказательство включения его значения, иначе - доказательство отсутствия значения.
+    pub fn proof(&self, key: &SparseKey) -> SparseMerkleProof<D> {
+        let mut bitmap = [0; 32];
+        let mut siblings = Vec::new();
+        for height in 0..SPARSE_TREE_DEPTH {
+            let sibling = self.node(height, &sibling_key(key, height));
+            if sibling != self.default_hashes[height] {
+                set_bit(&mut bitmap, height);
+                siblings.push(sibling);
+            }
+        }
+        SparseMerkleProof {
+            key: *key,
+            leaf: self.get(key),
+            bitmap,
+            siblings,
+            digest: self.digest.clone(),
+            default_hashes: self.default_hashes.clone(),
+        }
+    }
+
+    /// Записывает "лист" и пересчитывает узлы на пути от него к корню.
+    fn set_leaf(&mut self, key: &SparseKey, leaf: D::Node) {
+        let mut node = leaf;
+        self.set_node(0, *key, node);
+        for height in 0..SPARSE_TREE_DEPTH {
+            let sibling = self.node(height, &sibling_key(key, height));
+            node = if bit(key, height) {
+                hash_node(&self.digest, TreeMode::Classic, &sibling, &node)
+            } else {
+                hash_node(&self.digest, TreeMode::Classic, &node, &sibling)
+            };
+            self.set_node(height + 1, prefix(key, height + 1), node);
+        }
+    }
+
+    /// Возвращает узел на высоте `height` над "листьями", покрывающий ключ `key`.
+    fn node(&self, height: usize, key: &SparseKey) -> D::Node {
+        self.nodes
+            .get(&(height, prefix(key, height)))
+            .cloned()
+            .unwrap_or(self.default_hashes[height])
+    }
+
+    /// Сохраняет узел, либо удаляет его, если он совпадает с корнем пустого поддерева.
+    fn set_node(&mut self, height: usize, prefix: SparseKey, node: D::Node) {
+        if node == self.default_hashes[height] {
+            self.nodes.remove(&(height, prefix));
+        } else {
+            self.nodes.insert((height, prefix), node);
+        }
+    }
+}
+
+/// Вычисляет корни пустых поддеревьев высотой от 0 (пустой "лист") до 256.
+pub fn default_hashes<D>(digest: &D) -> Vec<D::Node>
+    where D: Hasher
+{
+    let mut hashes = Vec::with_capacity(SPARSE_TREE_DEPTH + 1);
+    hashes.push(D::Node::zeroed());
+    for height in 0..SPARSE_TREE_DEPTH {
+        let child = hashes[height];
+        hashes.push(hash_node(digest, TreeMode::Classic, &child, &child));
+    }
+    hashes
+}
+
+/// Возвращает бит ключа, определяющий, является ли узел на высоте `height` правым потомком.
+pub fn bit(key: &SparseKey, height: usize) -> bool {
+    key[31 - height / 8] & (1 << (height % 8)) != 0
+}
+
+/// Устанавливает бит ключа, соответствующий высоте `height`.
+pub fn set_bit(key: &mut SparseKey, height: usize) {
+    key[31 - height / 8] |= 1 << (height % 8);
+}
+
+/// Обнуляет младшие `height` бит ключа, оставляя путь до узла на высоте `height`.
+fn prefix(key: &SparseKey, height: usize) -> SparseKey {
+    let mut prefix = *key;
+    let cleared_bytes = height.min(SPARSE_TREE_DEPTH) / 8;
+    for byte in prefix.iter_mut().skip(32 - cleared_bytes) {
+        *byte = 0;
+    }
+    if cleared_bytes < 32 {
+        prefix[31 - cleared_bytes] &= 0xff << (height % 8);
+    }
+    prefix
+}
+
+/// Возвращает ключ соседнего узла на высоте `height`.
+fn sibling_key(key: &SparseKey, height: usize) -> SparseKey {
+    let mut sibling = prefix(key, height);
+    sibling[31 - height / 8] ^= 1 << (height % 8);
+    sibling
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate env_logger;
+
+    use std::sync::Arc;
+    use super::{SparseMerkleTree, SparseKey, default_hashes};
+    use hasher::Blake2b256;
+    use serialization_format::SerializationFormat;
+    use merkle_error::MerkleError;
+
+    fn key(value: u8) -> SparseKey {
+        let mut key = [0; 32];
+        key[0] = value;
+        key[31] = value;
+        key
+    }
+
+    #[test]
+    fn empty_tree() {
+        let _ = env_logger::init();
+        let sparse_tree: SparseMerkleTree = SparseMerkleTree::default();
+        assert!(sparse_tree.is_empty());
+        assert_eq!(default_hashes(&::crypto::sha2::Sha256::new())[256], sparse_tree.root());
+        let proof = sparse_tree.proof(&key(1));
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify_non_inclusion(&sparse_tree.root()));
+    }
+
+    #[test]
+    fn insert_update_delete() {
+        let _ = env_logger::init();
+        let mut sparse_tree: SparseMerkleTree = SparseMerkleTree::default();
+        let empty_root = sparse_tree.root();
+        assert_eq!(None, sparse_tree.insert(&key(1), &"a").unwrap());
+        let single_root = sparse_tree.root();
+        assert_eq!(None, sparse_tree.insert(&key(2), &"b").unwrap());
+        assert_eq!(None, sparse_tree.insert(&key(130), &"c").unwrap());
+        assert_eq!(3, sparse_tree.len());
+
+        let previous = sparse_tree.get(&key(2));
+        assert_eq!(previous, Some(sparse_tree.update(&key(2), &"d").unwrap()));
+        assert_eq!(Err(MerkleError::LeafNotFound), sparse_tree.update(&key(3), &"d"));
+
+        let mut reordered: SparseMerkleTree = SparseMerkleTree::default();
+        reordered.insert(&key(130), &"c").unwrap();
+        reordered.insert(&key(2), &"d").unwrap();
+        reordered.insert(&key(1), &"a").unwrap();
+        assert_eq!(reordered.root(), sparse_tree.root());
+
+        assert!(sparse_tree.delete(&key(2)).is_some());
+        assert!(sparse_tree.delete(&key(130)).is_some());
+        assert_eq!(None, sparse_tree.delete(&key(130)));
+        assert_eq!(single_root, sparse_tree.root());
+        sparse_tree.delete(&key(1));
+        assert_eq!(empty_root, sparse_tree.root());
+        assert!(sparse_tree.nodes.is_empty());
+    }
+
+    #[test]
+    fn empty_hash_clears_leaf() {
+        let _ = env_logger::init();
+        let mut sparse_tree: SparseMerkleTree = SparseMerkleTree::default();
+        let empty_root = sparse_tree.root();
+        assert_eq!(None, sparse_tree.insert_hash(&key(1), [0; 32]));
+        assert!(sparse_tree.is_empty());
+
+        sparse_tree.insert(&key(1), &"a").unwrap();
+        let previous = sparse_tree.get(&key(1));
+        assert!(previous.is_some());
+        assert_eq!(previous, sparse_tree.insert_hash(&key(1), [0; 32]));
+        assert_eq!(None, sparse_tree.get(&key(1)));
+        assert!(sparse_tree.is_empty());
+        assert_eq!(empty_root, sparse_tree.root());
+    }
+
+    #[test]
+    fn inclusion_and_non_inclusion_proofs() {
+        let _ = env_logger::init();
+        let mut sparse_tree = SparseMerkleTree::with_digest(Blake2b256::new());
+        sparse_tree.format = SerializationFormat::Json;
+        for value in 0..20u8 {
+            sparse_tree.insert(&key(value * 7), &value).unwrap();
+        }
+        let root = sparse_tree.root();
+        for value in 0..20u8 {
+            let proof = sparse_tree.proof(&key(value * 7));
+            assert!(proof.verify_value(&root, &value, &SerializationFormat::Json));
+            assert!(!proof.verify_value(&root, &(value + 1), &SerializationFormat::Json));
+            assert!(!proof.verify_non_inclusion(&root));
+        }
+        let proof = sparse_tree.proof(&key(8));
+        assert!(proof.verify_non_inclusion(&root));
+        assert!(!proof.verify_value(&root, &8u8, &SerializationFormat::Json));
+        assert!(Arc::ptr_eq(&sparse_tree.default_hashes, &proof.default_hashes));
+        let mut truncated = proof.clone();
+        truncated.default_hashes = Arc::new(proof.default_hashes[..1].to_vec());
+        assert_eq!(None, truncated.root(&[0; 32]));
+
+        sparse_tree.insert(&key(8), &8u8).unwrap();
+        assert!(!proof.verify_non_inclusion(&sparse_tree.root()));
+        assert!(sparse_tree.proof(&key(8))
+            .verify_value(&sparse_tree.root(), &8u8, &SerializationFormat::Json));
+    }
+
+    #[test]
+    fn tampered_proof() {
+        let _ = env_logger::init();
+        let mut sparse_tree: SparseMerkleTree = SparseMerkleTree::default();
+        sparse_tree.insert(&key(1), &"a").unwrap();
+        sparse_tree.insert(&key(2), &"b").unwrap();
+        let root = sparse_tree.root();
+        let proof = sparse_tree.proof(&key(1));
+
+        let mut wrong_key = proof.clone();
+        wrong_key.key = key(3);
+        assert!(!wrong_key.verify_value(&root, &"a", &SerializationFormat::MsgPack));
+
+        let mut missing_sibling = proof.clone();
+        missing_sibling.siblings.pop();
+        assert_eq!(None, missing_sibling.root(&sparse_tree.get(&key(1)).unwrap()));
+
+        let mut tampered_sibling = proof.clone();
+        tampered_sibling.siblings[0][0] ^= 1;
+        assert!(!tampered_sibling.verify_value(&root, &"a", &SerializationFormat::MsgPack));
+        assert!(proof.verify_value(&root, &"a", &SerializationFormat::MsgPack));
+    }
+}
@@ -0,0 +1,47 @@
+//! Общие данные и вспомогательные функции для тестов.
+use std::env;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use odd_node_policy::OddNodePolicy;
+
+/// Данные "листьев" из тестов Certificate Transparency.
+pub const RFC6962_LEAVES: [&[u8]; 8] = [
+    b"",
+    b"\x00",
+    b"\x10",
+    b"\x20\x21",
+    b"\x30\x31",
+    b"\x40\x41\x42\x43",
+    b"\x50\x51\x52\x53\x54\x55\x56\x57",
+    b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+];
+
+/// Все правила обработки одиночных узлов.
+pub const ODD_NODE_POLICIES: [OddNodePolicy; 4] = [OddNodePolicy::Rehash,
+                                                   OddNodePolicy::Duplicate,
+                                                   OddNodePolicy::Promote,
+                                                   OddNodePolicy::ZeroPad];
+
+/// Разбирает шестнадцатеричную строку в байты.
+pub fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2).map(|index| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).unwrap()).collect()
+}
+
+/// Разбирает шестнадцатеричную строку в 32-байтный хэш.
+pub fn hash_from_hex(hex: &str) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&from_hex(hex));
+    hash
+}
+
+/// Запись, которую не удаётся сериализовать в JSON: ключи объекта должны быть строками.
+pub fn bad_record() -> BTreeMap<(i32, i32), &'static str> {
+    let mut record = BTreeMap::new();
+    record.insert((1, 2), "non-string key");
+    record
+}
+
+/// Путь во временном каталоге, уникальный для процесса.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("merkle_tree_{}_{}", name, ::std::process::id()))
+}
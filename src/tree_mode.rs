@@ -0,0 +1,30 @@
+/// Режим хэширования узлов дерева.
+/// Должен быть выбран до того, как в дерево будут добавлены "листья".
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum TreeMode {
+    /// Листья и узлы хэшируются без префиксов.
+    #[default]
+    Classic,
+    /// Режим совместимый с RFC 6962 (Certificate Transparency): листья хэшируются с префиксом
+    /// 0x00, узлы с префиксом 0x01. Правилу разбиения несбалансированного дерева из RFC
+    /// соответствует `OddNodePolicy::Promote`.
+    Rfc6962,
+}
+
+impl TreeMode {
+    /// Префикс, добавляемый перед данными "листа".
+    pub fn leaf_prefix(&self) -> &'static [u8] {
+        match *self {
+            TreeMode::Classic => &[],
+            TreeMode::Rfc6962 => &[0x00],
+        }
+    }
+
+    /// Префикс, добавляемый перед парой дочерних узлов.
+    pub fn node_prefix(&self) -> &'static [u8] {
+        match *self {
+            TreeMode::Classic => &[],
+            TreeMode::Rfc6962 => &[0x01],
+        }
+    }
+}
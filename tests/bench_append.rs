@@ -1,4 +1,5 @@
 #![feature(test)]
+#![allow(clippy::field_reassign_with_default)]
 extern crate test;
 extern crate merkle_tree;
 
@@ -8,11 +9,6 @@ mod tests {
     use test;
     use test::Bencher;
 
-    extern crate crypto;
-
-    use self::crypto::sha2::Sha256;
-    use self::crypto::digest::Digest;
-
     #[bench]
     fn append_10000_bincode_parallel(b: &mut Bencher) {
         b.iter(|| {
@@ -20,9 +16,9 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = true;
-            merkle_tree.push(&["a"]);
+            merkle_tree.push(&["a"]).unwrap();
             merkle_tree.build().unwrap();
-            (0..n).fold((), |_, index| { merkle_tree.push(&[index]); });
+            (0..n).fold((), |_, index| { merkle_tree.push(&[index]).unwrap(); });
             merkle_tree.build().unwrap();
         })
     }
@@ -34,10 +30,40 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = true;
-            merkle_tree.push(&["a"]);
+            merkle_tree.push(&["a"]).unwrap();
+            merkle_tree.build().unwrap();
+            (0..n).fold((), |_, index| { merkle_tree.push(&[index]).unwrap(); });
+            merkle_tree.build().unwrap()
+        })
+    }
+
+    #[bench]
+    fn extend_10000_bincode_parallel(b: &mut Bencher) {
+        b.iter(|| {
+            let n = self::test::black_box(10000);
+            let mut merkle_tree: MerkleTree = MerkleTree::default();
+            merkle_tree.format = SerializationFormat::Bincode;
+            merkle_tree.parallel = true;
+            merkle_tree.push(&["a"]).unwrap();
+            merkle_tree.build().unwrap();
+            let batch: Vec<[usize; 1]> = (0..n).map(|index| [index]).collect();
+            merkle_tree.extend(&batch).unwrap();
+            merkle_tree.build().unwrap();
+        })
+    }
+
+    #[bench]
+    fn extend_100000_bincode_parallel(b: &mut Bencher) {
+        b.iter(|| {
+            let n = self::test::black_box(100000);
+            let mut merkle_tree: MerkleTree = MerkleTree::default();
+            merkle_tree.format = SerializationFormat::Bincode;
+            merkle_tree.parallel = true;
+            merkle_tree.push(&["a"]).unwrap();
             merkle_tree.build().unwrap();
-            (0..n).fold((), |_, index| { merkle_tree.push(&[index]); });
+            let batch: Vec<[usize; 1]> = (0..n).map(|index| [index]).collect();
+            merkle_tree.extend(&batch).unwrap();
             merkle_tree.build().unwrap()
         })
     }
-}
\ No newline at end of file
+}
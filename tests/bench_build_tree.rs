@@ -1,4 +1,5 @@
 #![feature(test)]
+#![allow(clippy::field_reassign_with_default)]
 extern crate test;
 extern crate merkle_tree;
 
@@ -24,7 +25,7 @@ mod tests {
         b.iter(|| {
             let n = self::test::black_box(1);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -34,7 +35,7 @@ mod tests {
         b.iter(|| {
             let n = self::test::black_box(10);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -44,7 +45,7 @@ mod tests {
         b.iter(|| {
             let n = self::test::black_box(100);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -54,7 +55,7 @@ mod tests {
         b.iter(|| {
                    let n = self::test::black_box(1000);
                    let mut merkle_tree: MerkleTree = MerkleTree::default();
-                   (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+                   (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
                    merkle_tree.build().unwrap()
                })
     }
@@ -65,7 +66,7 @@ mod tests {
         b.iter(|| {
                    let n = self::test::black_box(10000);
                    let mut merkle_tree: MerkleTree = MerkleTree::default();
-                   (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+                   (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
                    merkle_tree.build().unwrap()
                })
     }
@@ -75,7 +76,7 @@ mod tests {
         b.iter(|| {
             let n = self::test::black_box(100000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -85,7 +86,7 @@ mod tests {
         b.iter(|| {
             let n = self::test::black_box(1000000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -96,7 +97,7 @@ mod tests {
             let n = self::test::black_box(1);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -107,7 +108,7 @@ mod tests {
             let n = self::test::black_box(10);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -118,7 +119,7 @@ mod tests {
             let n = self::test::black_box(100);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -129,7 +130,7 @@ mod tests {
             let n = self::test::black_box(1000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -141,7 +142,7 @@ mod tests {
             let n = self::test::black_box(10000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -152,7 +153,7 @@ mod tests {
             let n = self::test::black_box(100000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -163,7 +164,7 @@ mod tests {
             let n = self::test::black_box(1000000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -183,7 +184,7 @@ mod tests {
             let n = self::test::black_box(1);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -194,7 +195,7 @@ mod tests {
             let n = self::test::black_box(10);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -205,7 +206,7 @@ mod tests {
             let n = self::test::black_box(100);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -216,7 +217,7 @@ mod tests {
             let n = self::test::black_box(1000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -228,7 +229,7 @@ mod tests {
             let n = self::test::black_box(10000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -239,7 +240,7 @@ mod tests {
             let n = self::test::black_box(100000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -250,7 +251,7 @@ mod tests {
             let n = self::test::black_box(1000000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -262,7 +263,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -274,7 +275,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -286,7 +287,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -298,7 +299,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -311,7 +312,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -323,7 +324,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -335,7 +336,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::MsgPack;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -354,7 +355,7 @@ mod tests {
             let n = self::test::black_box(1);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -365,7 +366,7 @@ mod tests {
             let n = self::test::black_box(10);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -376,7 +377,7 @@ mod tests {
             let n = self::test::black_box(100);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -387,7 +388,7 @@ mod tests {
             let n = self::test::black_box(1000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -399,7 +400,7 @@ mod tests {
             let n = self::test::black_box(10000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -410,7 +411,7 @@ mod tests {
             let n = self::test::black_box(100000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -421,7 +422,7 @@ mod tests {
             let n = self::test::black_box(1000000);
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -433,7 +434,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -445,7 +446,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -457,7 +458,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -469,7 +470,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -482,7 +483,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -494,7 +495,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -506,7 +507,7 @@ mod tests {
             let mut merkle_tree: MerkleTree = MerkleTree::default();
             merkle_tree.format = SerializationFormat::Bincode;
             merkle_tree.parallel = false;
-            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]); });
+            (0..n).fold((), |_, _| { merkle_tree.push(&["a"]).unwrap(); });
             merkle_tree.build().unwrap()
         })
     }
@@ -515,7 +516,7 @@ mod tests {
     fn raw_hash_2000000_sequential(b: &mut Bencher) {
         b.iter(|| {
             let n = self::test::black_box(2000000);
-            (0..n).fold((), |a, b| {
+            (0..n).fold((), |_, _| {
                 let mut result = [0; 32];
                 let mut sha = Sha256::new();
                 sha.input(&[0; 32]);